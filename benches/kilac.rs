@@ -14,17 +14,17 @@ fn lexer(c: &mut Criterion) {
 }
 
 fn parser(c: &mut Criterion) {
-    c.bench_function("ast min", |b| b.iter(|| parse(lex("min(5, 2)".into()))));
-    c.bench_function("ast minmax", |b| b.iter(|| parse(lex("min(5, max(2, -2))".into()))));
-    c.bench_function("ast empty min", |b| b.iter(|| parse_fn(lex("min(5, 2)".into()), empty, EmptyCtx)));
-    c.bench_function("ast empty minmax", |b| b.iter(|| parse_fn(lex("min(5, max(2, -2))".into()), empty, EmptyCtx)));
+    c.bench_function("ast min", |b| b.iter(|| parse(lex("min(5, 2)").unwrap(), EmptyCtx)));
+    c.bench_function("ast minmax", |b| b.iter(|| parse(lex("min(5, max(2, -2))").unwrap(), EmptyCtx)));
+    c.bench_function("ast empty min", |b| b.iter(|| parse_fn(lex("min(5, 2)").unwrap(), empty, EmptyCtx)));
+    c.bench_function("ast empty minmax", |b| b.iter(|| parse_fn(lex("min(5, max(2, -2))").unwrap(), empty, EmptyCtx)));
 }
 
 fn evalb(c: &mut Criterion) {
     c.bench_function("calc min", |b| b.iter(|| calculate("min(5, 2)".into())));
     c.bench_function("calc minmax", |b| b.iter(|| calculate("min(5, max(2, -2))".into())));
-    c.bench_function("calc empty min", |b| b.iter(|| eval(parse_fn(lex("min(5, 2)".into()), empty, EmptyCtx).ok().unwrap())));
-    c.bench_function("calc empty minmax", |b| b.iter(|| eval(parse_fn(lex("min(5, max(2, -2))".into()), empty, EmptyCtx).ok().unwrap())));
+    c.bench_function("calc empty min", |b| b.iter(|| eval(parse_fn(lex("min(5, 2)").unwrap(), empty, EmptyCtx).ok().unwrap(), EmptyCtx)));
+    c.bench_function("calc empty minmax", |b| b.iter(|| eval(parse_fn(lex("min(5, max(2, -2))").unwrap(), empty, EmptyCtx).ok().unwrap(), EmptyCtx)));
 }
 
 criterion_group!(benches, lexer, parser, evalb);
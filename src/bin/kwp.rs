@@ -0,0 +1,71 @@
+//! Kwp server. Listens on the address given as the argument, by default
+//! 127.0.0.1:7878, and serves kwp over TCP, one command per line, or over
+//! websockets with `--websocket`.
+//!
+//! Usage: `kwp [--websocket] [--sessions <file>] [--expire <minutes>] [--workers <n>] [<address>]`
+//!
+//! With `--sessions` the sessions are kept in the file and survive restarts,
+//! and with `--expire` sessions unused for the given time are removed.
+//! Calculations are run by `--workers` threads, by default one per CPU.
+
+extern crate kilac;
+
+use std::env;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use kilac::kwp::queue::Queue;
+use kilac::kwp::session::Sessions;
+use kilac::kwp::{server, websocket};
+
+fn usage() -> ! {
+    eprintln!("usage: kwp [--websocket] [--sessions <file>] [--expire <minutes>] [--workers <n>] [<address>]");
+    process::exit(2);
+}
+
+fn main() {
+    let mut ws = false;
+    let mut file = None;
+    let mut expire = None;
+    let mut workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut addr = "127.0.0.1:7878".to_string();
+    let mut args = env::args().skip(1);
+    while let Some(a) = args.next() {
+        match &a[..] {
+            "--websocket" => ws = true,
+            "--sessions" => file = Some(args.next().unwrap_or_else(|| usage())),
+            "--expire" => match args.next().and_then(|m| m.parse::<u64>().ok()) {
+                Some(m) => expire = Some(Duration::from_secs(m * 60)),
+                None => usage(),
+            },
+            "--workers" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => workers = n,
+                _ => usage(),
+            },
+            _ if a.starts_with("--") => usage(),
+            _ => addr = a,
+        }
+    }
+    let mut sessions = match file {
+        Some(f) => Sessions::load(f).unwrap_or_else(|e| {
+            eprintln!("kwp: {}", e);
+            process::exit(1);
+        }),
+        None => Sessions::new(),
+    };
+    sessions.max_idle = expire;
+    let queue = Arc::new(Queue::new(workers, Arc::new(Mutex::new(sessions))));
+    let res = if ws {
+        eprintln!("kwp listening for websockets on {}", addr);
+        websocket::listen(&addr[..], queue)
+    } else {
+        eprintln!("kwp listening on {}", addr);
+        server::listen(&addr[..], queue)
+    };
+    if let Err(e) = res {
+        eprintln!("kwp: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,150 @@
+//! Checks formulas without calculating them. A formula is lexed and parsed,
+//! every getter is resolved by the caller and the shapes of the values are
+//! followed through the functions, so a formula that passes can only fail
+//! on the inputs of a team, e.g. by dividing by zero.
+
+use super::ctx::EmptyCtx;
+use super::error::KilaError;
+use super::getter::Getter;
+use super::lexer::lex;
+use super::parser::{applicators, parse_fn, Ast, Fun, Signature};
+use super::span::Span;
+use super::value::Value;
+
+/// Shape of the value of a formula, known without calculating it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    /// Number, time, duration or boolean.
+    Single,
+    /// Value of every team of a series, or a list written in the formula.
+    List,
+    /// `(x, y)`, a point of `interp`.
+    Pair,
+    /// Part of the formula that already had an error.
+    Unknown,
+}
+
+impl Shape {
+    fn is_list(self) -> bool {
+        self == Shape::List || self == Shape::Pair
+    }
+}
+
+fn mismatch(expected: &'static str, found: Shape, span: Span) -> KilaError {
+    KilaError::TypeMismatch {
+        expected,
+        found: if found.is_list() { "list" } else { "number" },
+        span,
+    }
+}
+
+/// Checks formula `s`. `resolve` gives the shape of the value of a getter
+/// or an error if the getter does not name an input. Returns the formula
+/// in normal form, see `Ast`, or every error found. Only the first error
+/// of parsing is found, as parsing stops there.
+pub fn check<F>(s: &str, resolve: F) -> Result<String, Vec<KilaError>>
+where
+    F: Fn(&Getter) -> Result<Shape, KilaError>,
+{
+    let lexed = lex(s)?;
+    let ast = parse_fn(lexed, applicators::filter, EmptyCtx).map_err(|e| vec![e])?;
+    let mut errors = Vec::new();
+    let res = shape(&ast, &resolve, &mut errors);
+    if res.is_list() {
+        errors.push(mismatch("number", res, ast.span()));
+    }
+    if errors.is_empty() {
+        Ok(ast.to_string())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Shape of the value of `ast`, following the rules of `eval`. Errors are
+/// pushed to `errors` and the shape of the erroneous part is unknown.
+fn shape<F>(ast: &Ast, resolve: &F, errors: &mut Vec<KilaError>) -> Shape
+where
+    F: Fn(&Getter) -> Result<Shape, KilaError>,
+{
+    let (args, fun) = match *ast {
+        Ast::Empty(_) => return Shape::Single,
+        Ast::Leaf(Value::List(_), _) => return Shape::List,
+        Ast::Leaf(..) => return Shape::Single,
+        Ast::Get(ref g, span) => {
+            return resolve(g).unwrap_or_else(|e| {
+                errors.push(e.at(span));
+                Shape::Unknown
+            })
+        }
+        Ast::Node(ref args, fun, _) => (args, fun),
+    };
+    let shapes: Vec<Shape> = args.iter().map(|a| shape(a, resolve, errors)).collect();
+    if shapes.contains(&Shape::Unknown) {
+        return Shape::Unknown;
+    }
+    let variadic = matches!(fun.signature(), Signature::Variadic(_));
+    // Arguments that must be single values.
+    let single = match fun {
+        Fun::SS | Fun::Interp => 1,
+        _ if variadic || fun.binding().is_some() || fun == Fun::Pair => 0,
+        _ => args.len(),
+    };
+    for (a, s) in args.iter().zip(&shapes).take(single) {
+        if s.is_list() {
+            errors.push(mismatch("number", *s, a.span()));
+        }
+    }
+    match fun {
+        Fun::List => Shape::List,
+        Fun::Pair => Shape::Pair,
+        Fun::Interp => {
+            for (a, s) in args.iter().zip(&shapes).skip(1) {
+                // Lists of the right length are points too.
+                if *s == Shape::Single {
+                    errors.push(mismatch("pair", *s, a.span()));
+                }
+            }
+            Shape::Single
+        }
+        _ if fun.binding().is_some() && shapes.iter().any(|s| s.is_list()) => Shape::List,
+        _ => Shape::Single,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calc::getter::Scope;
+
+    /// Inputs a and b exist, of the team or of the whole series.
+    fn resolve(g: &Getter) -> Result<Shape, KilaError> {
+        match *g {
+            Getter::Participation => Ok(Shape::List),
+            Getter::Input(ref p) if p.input == "a" || p.input == "b" => {
+                Ok(if p.scope == Scope::Series { Shape::List } else { Shape::Single })
+            }
+            Getter::Input(_) => Err(KilaError::UnknownIdentifier(g.to_string(), Span::default())),
+            _ => Ok(Shape::Single),
+        }
+    }
+
+    fn codes(s: &str) -> Vec<(u32, Span)> {
+        check(s, resolve).unwrap_err().iter().map(|e| (e.code(), e.span())).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(Ok("a / max(muk.a) * 10".into()), check("a/suurin(.a*muk)*10", resolve));
+        assert_eq!(Ok("interp(a, (0, 0), (10, .b.a.vartio))".into()), check("interp(a,(0,0),(10,.b.a.vartio))", resolve));
+        assert_eq!(Ok("ss(a, .a - 1)".into()), check("ss(a, .a-1)", resolve));
+    }
+    #[test]
+    fn test_errors() {
+        assert_eq!(vec![(104, Span::new(0, 1)), (104, Span::new(8, 10))], codes("c + max(.d)"));
+        assert_eq!(vec![(200, Span::new(4, 6))], codes("abs(.a)"));
+        assert_eq!(vec![(200, Span::new(0, 6))], codes(".a + 1"));
+        assert_eq!(vec![(200, Span::new(10, 11))], codes("interp(5, 1, (10, 5))"));
+        assert_eq!(vec![(100, Span::new(2, 3)), (100, Span::new(6, 7))], codes("1 ; 2 ; 3"));
+        assert_eq!(vec![(103, Span::new(0, 9))], codes("interp(1)"));
+    }
+}
@@ -1,21 +1,33 @@
 //! Context module. This module provides types that can deal with
 //! context and other things.
 
-use super::parser::Ast;
+use super::error::KilaError;
+use super::getter::Getter;
+use super::value::Value;
 /// Empty object so that bunch of simpler internals can be implemented
 #[derive(Debug, Clone)]
 pub struct EmptyCtx;
 
-/// Classes that implement KilaCtx returns AST node,
-/// that corresponds to the type of getter.
-/// Thus either List or Leaf is returned.
-/// Can also return empty, which signals for empty getter.
+/// Classes that implement KilaCtx returns the value of a getter.
+/// Thus either a list or a single value is returned.
+/// Can also return empty, which signals for an input that was not given.
+///
+/// To be Kipa-compatible, getters of the whole series (`.a`) return a list
+/// with an element for every team, empty for teams without the input.
+/// `..mukana` and `muk` return a list with 1 for teams taking part in the
+/// competition and empty for others, so multiplying with it leaves only
+/// the teams taking part. The basic applicator turns such multiplications
+/// into the getter of `.a` with the `participating` flag set, which returns
+/// `.a` with values of the teams not taking part empty. See `getter` for
+/// the other forms of getters.
+///
+/// `competition::ctx::CompetitionCtx` implements this over a competition.
 pub trait KilaCtx: Clone {
-    fn get(&self, String) -> Result<Ast, String>;
+    fn get(&self, getter: &Getter) -> Result<Value, KilaError>;
 }
 
 impl KilaCtx for EmptyCtx {
-    fn get(&self, _: String) -> Result<Ast, String> {
-        Ok(Ast::Empty)
+    fn get(&self, _: &Getter) -> Result<Value, KilaError> {
+        Ok(Value::Empty)
     }
 }
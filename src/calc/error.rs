@@ -0,0 +1,182 @@
+//! Errors of lexing, parsing and evaluating formulas. Every error points at
+//! the part of the formula that caused it and has a stable error code, which
+//! is the `<code>` of the kwp `error <code> <explanation>` message.
+
+use std::error::Error;
+use std::fmt;
+
+use super::parser::{Fun, Signature};
+use super::span::Span;
+
+/// Error returned by every fallible function of calc.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KilaError {
+    /// Input could not be split into tokens.
+    Lex(String, Span),
+    /// A parenthesis is missing its pair.
+    UnbalancedParens(Span),
+    /// Tokens are in an order that does not form an expression.
+    Syntax(String, Span),
+    /// Function got wrong number of arguments.
+    Arity {
+        fun: Fun,
+        expected: Signature,
+        found: usize,
+        span: Span,
+    },
+    /// Getter does not name anything in the context.
+    UnknownIdentifier(String, Span),
+    /// Function is known to the parser but can not be evaluated.
+    UnknownFunction(Fun, Span),
+    /// Getter is not a valid path to an input, e.g. `.a..b`.
+    MalformedGetter(String, Span),
+    /// Got a vector where a number was expected or vice versa.
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+        span: Span,
+    },
+    DivisionByZero(Span),
+    /// Context failed to resolve a getter.
+    Context(String, Span),
+}
+
+impl KilaError {
+    /// Stable error code of the error. These are listed in doc/protocol.md
+    /// and must never be renumbered.
+    pub fn code(&self) -> u32 {
+        match *self {
+            KilaError::Lex(..) => 100,
+            KilaError::UnbalancedParens(_) => 101,
+            KilaError::Syntax(..) => 102,
+            KilaError::Arity { .. } => 103,
+            KilaError::UnknownIdentifier(..) => 104,
+            KilaError::UnknownFunction(..) => 105,
+            KilaError::MalformedGetter(..) => 106,
+            KilaError::TypeMismatch { .. } => 200,
+            KilaError::DivisionByZero(_) => 201,
+            KilaError::Context(..) => 300,
+        }
+    }
+
+    /// Part of the formula this error points at.
+    pub fn span(&self) -> Span {
+        match *self {
+            KilaError::Lex(_, s) |
+            KilaError::UnbalancedParens(s) |
+            KilaError::Syntax(_, s) |
+            KilaError::UnknownIdentifier(_, s) |
+            KilaError::UnknownFunction(_, s) |
+            KilaError::MalformedGetter(_, s) |
+            KilaError::DivisionByZero(s) |
+            KilaError::Context(_, s) => s,
+            KilaError::Arity { span, .. } | KilaError::TypeMismatch { span, .. } => span,
+        }
+    }
+
+    /// Returns the same error pointing at `span` instead. Contexts do not
+    /// know where their getter was written, so eval places their errors.
+    pub fn at(self, span: Span) -> KilaError {
+        match self {
+            KilaError::Lex(m, _) => KilaError::Lex(m, span),
+            KilaError::UnbalancedParens(_) => KilaError::UnbalancedParens(span),
+            KilaError::Syntax(m, _) => KilaError::Syntax(m, span),
+            KilaError::Arity { fun, expected, found, .. } => KilaError::Arity {
+                fun,
+                expected,
+                found,
+                span,
+            },
+            KilaError::UnknownIdentifier(m, _) => KilaError::UnknownIdentifier(m, span),
+            KilaError::UnknownFunction(f, _) => KilaError::UnknownFunction(f, span),
+            KilaError::MalformedGetter(m, _) => KilaError::MalformedGetter(m, span),
+            KilaError::TypeMismatch { expected, found, .. } => KilaError::TypeMismatch {
+                expected,
+                found,
+                span,
+            },
+            KilaError::DivisionByZero(_) => KilaError::DivisionByZero(span),
+            KilaError::Context(m, _) => KilaError::Context(m, span),
+        }
+    }
+
+    /// Merges the errors of lexing a formula into one, so every one of
+    /// them is reported where only one error can be. The merged error
+    /// covers all of them.
+    pub fn merge(mut errors: Vec<KilaError>) -> KilaError {
+        if errors.len() == 1 {
+            return errors.remove(0);
+        }
+        let span = errors.iter().map(KilaError::span).fold(errors[0].span(), Span::to);
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|e| match *e {
+                KilaError::Lex(ref m, _) => m.clone(),
+                ref e => e.to_string(),
+            })
+            .collect();
+        KilaError::Lex(messages.join("; "), span)
+    }
+
+    /// The kwp error message `error <code> <explanation>`, without the
+    /// terminating newline.
+    pub fn kwp(&self) -> String {
+        format!("error {} {}", self.code(), self)
+    }
+}
+
+impl fmt::Display for KilaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KilaError::Lex(ref m, s) => write!(f, "{} at {}", m, s),
+            KilaError::UnbalancedParens(s) => write!(f, "unbalanced parenthesis at {}", s),
+            KilaError::Syntax(ref m, s) => write!(f, "{} at {}", m, s),
+            KilaError::Arity { fun, expected, found, span } => write!(
+                f,
+                "{:?} takes {} arguments but got {} at {}",
+                fun,
+                expected,
+                found,
+                span
+            ),
+            KilaError::UnknownIdentifier(ref m, s) => write!(f, "unknown identifier {} at {}", m, s),
+            KilaError::UnknownFunction(fun, s) => {
+                write!(f, "function {:?} can not be evaluated at {}", fun, s)
+            }
+            KilaError::MalformedGetter(ref m, s) => write!(f, "malformed getter: {} at {}", m, s),
+            KilaError::TypeMismatch { expected, found, span } => {
+                write!(f, "expected {} but got {} at {}", expected, found, span)
+            }
+            KilaError::DivisionByZero(s) => write!(f, "division by zero at {}", s),
+            KilaError::Context(ref m, s) => write!(f, "context: {} at {}", m, s),
+        }
+    }
+}
+
+impl Error for KilaError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kwp() {
+        let e = KilaError::DivisionByZero(Span::new(2, 5));
+        assert_eq!("error 201 division by zero at 2..5", e.kwp());
+    }
+    #[test]
+    fn test_merge() {
+        let e = KilaError::Lex("unexpected character ';'".into(), Span::new(1, 2));
+        assert_eq!(e.clone(), KilaError::merge(vec![e.clone()]));
+        let merged = KilaError::merge(vec![e, KilaError::Lex("unexpected character '#'".into(), Span::new(3, 4))]);
+        assert_eq!(
+            "unexpected character ';'; unexpected character '#' at 1..4",
+            merged.to_string()
+        );
+    }
+    #[test]
+    fn test_at() {
+        let e = KilaError::Context("no such input".into(), Span::default());
+        assert_eq!(Span::new(1, 3), e.at(Span::new(1, 3)).span());
+    }
+}
@@ -0,0 +1,218 @@
+//! Getters are the names in formulas that refer to inputs, like `a`, `.a`,
+//! `.b.a.vartio` or `..mukana`. The parser turns them into a `Getter`, so
+//! contexts do not need to parse them:
+//!
+//! ```text
+//! a                    input a of the team in the current subtask
+//! vartio               number of the team
+//! vartio.hylatty       whether the team is disqualified from the task
+//! vartio.sakko         penalty points of the team in the task
+//! .a                   input a of every team of the series
+//! .a.vartio, .a.12     input a of the team or of team number 12
+//! .b.a                 input a of subtask b of the current task
+//! ..start.c.a          input a of subtask c of task start
+//! muk, ..mukana        1 for teams taking part, empty for others
+//! muk.a                .a with the teams not taking part left empty
+//! sarja.tehtava.osatehtava.syote
+//!                      input of every team of another series
+//! ```
+//!
+//! Getters starting with dots may end with `.vartio` or a team number to
+//! get the value of a single team instead of the whole series.
+
+use std::fmt;
+
+use super::error::KilaError;
+use super::span::Span;
+
+/// Whose value a getter asks for.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Scope {
+    /// The team the formula is calculated for.
+    Own,
+    /// Every team of the series, in a list.
+    Series,
+    /// Team with this number.
+    Team(u32),
+}
+
+/// Path to an input. Missing parts are the ones of the formula.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Path {
+    pub scope: Scope,
+    /// Only given together with task and subtask.
+    pub series: Option<String>,
+    /// Only given together with subtask.
+    pub task: Option<String>,
+    pub subtask: Option<String>,
+    pub input: String,
+    /// Leave teams not taking part empty, `muk.a` or `.a*muk`.
+    pub participating: bool,
+}
+
+/// Structured getter.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Getter {
+    /// `vartio`, number of the team.
+    Number,
+    /// `muk` or `..mukana`.
+    Participation,
+    /// `vartio.hylatty`, true if the team is disqualified from the task.
+    Disqualified,
+    /// `vartio.sakko`, penalty points of the team in the task.
+    Penalty,
+    Input(Path),
+}
+
+impl Getter {
+    /// Parses getter `s`. Errors have no span, the parser places them.
+    pub fn parse(s: &str) -> Result<Getter, KilaError> {
+        let err = |m: &str| Err(KilaError::MalformedGetter(format!("{} in {}", m, s), Span::default()));
+        match s {
+            "vartio" => return Ok(Getter::Number),
+            "muk" | "..mukana" => return Ok(Getter::Participation),
+            "vartio.hylatty" => return Ok(Getter::Disqualified),
+            "vartio.sakko" => return Ok(Getter::Penalty),
+            _ => {}
+        }
+        let (participating, body) = if s.starts_with("muk.") {
+            (true, &s[3..])
+        } else {
+            (false, s)
+        };
+        let dots = body.chars().take_while(|c| *c == '.').count();
+        if dots > 2 {
+            return err("too many dots");
+        }
+        let mut parts: Vec<&str> = body[dots..].split('.').collect();
+        if parts.iter().any(|p| p.is_empty()) {
+            return err("empty name");
+        }
+        // The dot of `muk.s.t.o.a` separates a series, not a scope.
+        let dots = if participating && dots == 1 && parts.len() == 4 { 0 } else { dots };
+        let mut scope = if dots == 0 { Scope::Own } else { Scope::Series };
+        if dots > 0 && parts.len() > 1 {
+            let last = parts[parts.len() - 1];
+            if last == "vartio" {
+                scope = Scope::Own;
+                parts.pop();
+            } else if let Ok(n) = last.parse() {
+                scope = Scope::Team(n);
+                parts.pop();
+            }
+        }
+        let own = |p: &str| Some(String::from(p));
+        let (series, task, subtask) = match (dots, parts.len()) {
+            (0, 1) | (1, 1) => (None, None, None),
+            (1, 2) => (None, None, own(parts[0])),
+            (2, 3) => (None, own(parts[0]), own(parts[1])),
+            (0, 4) => {
+                scope = Scope::Series;
+                (own(parts[0]), own(parts[1]), own(parts[2]))
+            }
+            _ => return err("malformed path"),
+        };
+        Ok(Getter::Input(Path {
+            scope,
+            series,
+            task,
+            subtask,
+            input: parts[parts.len() - 1].into(),
+            participating,
+        }))
+    }
+}
+
+/// Writes the getter the way it is written in a formula, so that parsing
+/// it again gives the same getter.
+impl fmt::Display for Getter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let p = match *self {
+            Getter::Number => return write!(f, "vartio"),
+            Getter::Participation => return write!(f, "muk"),
+            Getter::Disqualified => return write!(f, "vartio.hylatty"),
+            Getter::Penalty => return write!(f, "vartio.sakko"),
+            Getter::Input(ref p) => p,
+        };
+        if p.participating {
+            write!(f, "muk")?;
+        }
+        if let (Some(s), Some(t), Some(st)) = (&p.series, &p.task, &p.subtask) {
+            return write!(f, "{}{}.{}.{}.{}", if p.participating { "." } else { "" }, s, t, st, p.input);
+        }
+        if !p.participating && p.scope == Scope::Own && p.subtask.is_none() {
+            return write!(f, "{}", p.input);
+        }
+        match (&p.task, &p.subtask) {
+            (Some(t), Some(st)) => write!(f, "..{}.{}.{}", t, st, p.input)?,
+            (_, Some(st)) => write!(f, ".{}.{}", st, p.input)?,
+            _ => write!(f, ".{}", p.input)?,
+        }
+        match p.scope {
+            Scope::Own => write!(f, ".vartio"),
+            Scope::Team(n) => write!(f, ".{}", n),
+            Scope::Series => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(scope: Scope, task: Option<&str>, subtask: Option<&str>, input: &str) -> Getter {
+        Getter::Input(Path {
+            scope,
+            series: None,
+            task: task.map(String::from),
+            subtask: subtask.map(String::from),
+            input: input.into(),
+            participating: false,
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let p = |s: &str| Getter::parse(s).unwrap();
+        assert_eq!(Getter::Number, p("vartio"));
+        assert_eq!(Getter::Participation, p("..mukana"));
+        assert_eq!(Getter::Disqualified, p("vartio.hylatty"));
+        assert_eq!(Getter::Penalty, p("vartio.sakko"));
+        assert_eq!(path(Scope::Own, None, None, "a"), p("a"));
+        assert_eq!(path(Scope::Own, None, None, "a"), p(".a.vartio"));
+        assert_eq!(path(Scope::Series, None, None, "a"), p(".a"));
+        assert_eq!(path(Scope::Team(12), None, None, "a"), p(".a.12"));
+        assert_eq!(path(Scope::Own, None, Some("b"), "a"), p(".b.a.vartio"));
+        assert_eq!(path(Scope::Series, None, Some("b"), "a"), p(".b.a"));
+        assert_eq!(path(Scope::Own, Some("start"), Some("c"), "a"), p("..start.c.a.vartio"));
+        assert_eq!(
+            Getter::Input(Path {
+                scope: Scope::Series,
+                series: Some("s".into()),
+                task: Some("t".into()),
+                subtask: Some("o".into()),
+                input: "a".into(),
+                participating: true,
+            }),
+            p("muk.s.t.o.a")
+        );
+    }
+    #[test]
+    fn test_malformed() {
+        let code = |s: &str| Getter::parse(s).map_err(|e| e.code());
+        assert_eq!(Err(106), code("a.b"));
+        assert_eq!(Err(106), code("...a"));
+        assert_eq!(Err(106), code(".a..b"));
+        assert_eq!(Err(106), code("a."));
+        assert_eq!(Err(106), code("..a.b"));
+        assert_eq!(Err(106), code(".a.b.c.d"));
+        assert_eq!(Err(106), code("vartio.a"));
+    }
+    #[test]
+    fn test_display() {
+        for s in &["a", "vartio", "muk", ".a", ".a.12", ".b.a", ".b.a.vartio", "..t.b.a",
+                   "..t.b.a.vartio", "muk.a", "muk.a.vartio", "muk..t.b.a", "s.t.b.a", "muk.s.t.b.a", "vartio.hylatty", "vartio.sakko"] {
+            assert_eq!(*s, Getter::parse(s).unwrap().to_string());
+        }
+    }
+}
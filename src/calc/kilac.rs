@@ -1 +1,61 @@
 //! This submodule hosts all functions and operations specific to kila.
+
+use super::super::kipac;
+
+/// Piecewise linear interpolation through `points`, given as `(x, y)` in
+/// any order. Like `interpoloi` the result is clamped: below the smallest x
+/// it is the y of that point and above the largest x the y of that one.
+/// Returns NaN if there are no points.
+pub fn interp(x: f64, mut points: Vec<(f64, f64)>) -> f64 {
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(f), Some(l)) => (*f, *l),
+        _ => return f64::NAN,
+    };
+    if x <= first.0 {
+        return first.1;
+    }
+    if x >= last.0 {
+        return last.1;
+    }
+    for w in points.windows(2) {
+        let ((x1, y1), (x2, y2)) = (w[0], w[1]);
+        if x1 <= x && x < x2 {
+            return kipac::interpoloi(x, x1, y1, x2, y2);
+        }
+    }
+    last.1
+}
+
+/// Rank ("sija") of `x` among `xs`, largest first: 1 plus the number of
+/// values larger than `x`, so ties share the better rank. Missing inputs
+/// are skipped like in aggregates. For smaller is better, negate both.
+pub fn ss(x: f64, xs: Vec<Option<f64>>) -> f64 {
+    1.0 + xs.into_iter().flatten().filter(|v| *v > x).count() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interp() {
+        let p = vec![(0.0, 0.0), (10.0, 5.0), (20.0, 6.0)];
+        assert_eq!(2.5, interp(5.0, p.clone()));
+        assert_eq!(5.5, interp(15.0, p.clone()));
+        assert_eq!(5.0, interp(10.0, p.clone()));
+        assert_eq!(0.0, interp(-3.0, p.clone()));
+        assert_eq!(6.0, interp(30.0, p));
+        assert_eq!(2.5, interp(5.0, vec![(10.0, 5.0), (0.0, 0.0)]));
+        assert_eq!(4.0, interp(1.0, vec![(3.0, 4.0)]));
+        assert!(interp(1.0, vec![]).is_nan());
+    }
+    #[test]
+    fn test_ss() {
+        let xs = vec![Some(12.0), Some(8.0), None, Some(20.0), Some(12.0)];
+        assert_eq!(1.0, ss(20.0, xs.clone()));
+        assert_eq!(2.0, ss(12.0, xs.clone()));
+        assert_eq!(4.0, ss(8.0, xs.clone()));
+        assert_eq!(1.0, ss(3.0, vec![None]));
+    }
+}
@@ -1,10 +1,18 @@
 use std::f64::consts::{E, PI};
+use super::error::KilaError;
+use super::span::{Span, Spanned};
 
 /// The lexer of Kila. This function lexes incoming string into a fully fledged
-/// token list. See Token.
-pub fn lex(s: &str) -> Vec<Token> {
+/// token list. See Token. Every token carries its span in `s`.
+/// Returns every problem found in `s` if it can not be lexed.
+pub fn lex(s: &str) -> Result<Vec<Spanned<Token>>, Vec<KilaError>> {
     let mut lexer = Lexer::new(s);
-    lexer.lex()
+    let res = lexer.lex();
+    if lexer.errors.is_empty() {
+        Ok(res)
+    } else {
+        Err(lexer.errors)
+    }
 }
 
 /// A lex token. Lexer returns a list of these. Not copy because of the
@@ -58,6 +66,8 @@ pub enum Token {
     Ge,
     Expr(String),
     Num(f64),
+    /// Clock time `hh:mm` or `hh:mm:ss` in seconds since midnight.
+    Time(f64),
     Empty,
     Plus,
     Minus,
@@ -68,6 +78,7 @@ pub enum Token {
 struct Lexer {
     pos: usize,
     inp: String,
+    errors: Vec<KilaError>,
 }
 
 impl Lexer {
@@ -75,6 +86,7 @@ impl Lexer {
         Lexer {
             pos: 0,
             inp: String::from(inp),
+            errors: Vec::new(),
         }
     }
     fn next_char(&self) -> char {
@@ -85,10 +97,8 @@ impl Lexer {
     }
 
     fn consume_char(&mut self) -> char {
-        let mut iter = self.inp[self.pos..].char_indices();
-        let (_, cur) = iter.next().unwrap();
-        let (nxt, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += nxt;
+        let cur = self.next_char();
+        self.pos += cur.len_utf8();
         cur
     }
 
@@ -108,13 +118,43 @@ impl Lexer {
     }
 
     fn get_expr(&mut self) -> String {
-        self.consume_while(|c| {
-            c.is_alphanumeric() || c == '.' || c == '=' || c == '>' || c == '<' || c == '!'
-        })
+        self.consume_while(|c| c.is_alphanumeric() || c == '.')
+    }
+
+    /// Lexes the rest of a clock time whose hours are already consumed.
+    fn get_time(&mut self, hours: &str) -> Result<Token, String> {
+        let mut parts = vec![hours.to_string()];
+        while parts.len() < 3 && !self.eof() && self.next_char() == ':' {
+            self.consume_char();
+            parts.push(self.consume_while(|c| c.is_ascii_digit()));
+        }
+        let s = parts.join(":");
+        let nums: Vec<u32> = parts.iter().filter_map(|p| p.parse().ok()).collect();
+        if nums.len() != parts.len() || nums[0] > 23 || nums[1..].iter().any(|&n| n > 59) {
+            return Err(format!("malformed time {}", s));
+        }
+        let secs = nums.iter().zip(&[3600, 60, 1]).map(|(n, m)| n * m).sum::<u32>();
+        Ok(Token::Time(f64::from(secs)))
+    }
+
+    fn error(&mut self, msg: String, start: usize) {
+        self.errors.push(KilaError::Lex(msg, Span::new(start, self.pos)));
+    }
+
+    fn parse_cmp(&mut self, op: &str) -> Result<Token, String> {
+        match op {
+            "==" => Ok(Token::Eq),
+            "!=" => Ok(Token::Neq),
+            ">=" => Ok(Token::Ge),
+            "<=" => Ok(Token::Le),
+            ">" => Ok(Token::Gt),
+            "<" => Ok(Token::Lt),
+            _ => Err(format!("unknown operator {}", op)),
+        }
     }
 
-    fn parse_expr(&mut self, expr: &str) -> Token {
-        match expr {
+    fn parse_expr(&mut self, expr: &str) -> Result<Token, String> {
+        Ok(match expr {
             "aikavali" => Token::Aikavali,
             "abs" => Token::Abs,
             "log" => Token::Log,
@@ -144,74 +184,106 @@ impl Lexer {
             "med" => Token::Med,
             "kesk" | "mean" => Token::Kesk,
             "ss" => Token::SS,
-            "==" => Token::Eq,
-            "!=" => Token::Neq,
-            ">=" => Token::Ge,
-            "<=" => Token::Le,
-            ">" => Token::Lt,
-            "<" => Token::Gt,
             _ => {
+                let mut chars = expr.chars();
+                let first = chars.next().unwrap_or(' ');
+                let second = chars.next().unwrap_or(' ');
                 let num = expr.parse::<f64>();
-                match num {
-                    Ok(val) => Token::Num(val),
-                    Err(_) => Token::Expr(String::from(expr)),
+                if first.is_ascii_digit() || first == '.' && second.is_ascii_digit() {
+                    match num {
+                        Ok(val) => Token::Num(val),
+                        Err(_) => return Err(format!("malformed number {}", expr)),
+                    }
+                } else {
+                    Token::Expr(String::from(expr))
                 }
             }
-        }
+        })
+    }
+    fn push(&self, res: &mut Vec<Spanned<Token>>, t: Token, start: usize) {
+        res.push(Spanned::new(t, Span::new(start, self.pos)));
     }
-    pub fn lex(&mut self) -> Vec<Token> {
-        let mut res: Vec<Token> = Vec::new();
+    pub fn lex(&mut self) -> Vec<Spanned<Token>> {
+        let mut res: Vec<Spanned<Token>> = Vec::new();
+        self.consume_whitespace();
+        if self.eof() {
+            self.error("empty formula".into(), 0);
+        }
         while !self.eof() {
-            self.consume_whitespace();
+            let start = self.pos;
             match self.next_char() {
                 '(' => {
                     self.consume_char();
-                    res.push(Token::ParL);
+                    self.push(&mut res, Token::ParL, start);
+                }
+                ')' => {
+                    self.consume_char();
+                    self.push(&mut res, Token::ParR, start);
                 }
-                ')' | ']' => {
+                ']' => {
                     self.consume_char();
-                    res.push(Token::ParR);
+                    self.push(&mut res, Token::BrackR, start);
                 }
                 '[' => {
                     self.consume_char();
-                    res.push(Token::List);
-                    res.push(Token::ParL);
+                    self.push(&mut res, Token::List, start);
+                    self.push(&mut res, Token::BrackL, start);
                 }
                 '+' => {
                     self.consume_char();
-                    res.push(Token::Add);
+                    self.push(&mut res, Token::Add, start);
                 }
                 '-' => {
                     self.consume_char();
-                    res.push(Token::Sub);
+                    self.push(&mut res, Token::Sub, start);
                 }
                 '*' => {
                     self.consume_char();
-                    res.push(Token::Mul);
+                    self.push(&mut res, Token::Mul, start);
                 }
                 '/' => {
                     self.consume_char();
-                    res.push(Token::Div);
+                    self.push(&mut res, Token::Div, start);
                 }
                 '^' => {
                     self.consume_char();
-                    res.push(Token::Ipow);
+                    self.push(&mut res, Token::Ipow, start);
                 }
                 '%' => {
                     self.consume_char();
-                    let tmp = res.pop().unwrap();
-                    res.push(Token::Imod);
-                    res.push(tmp);
+                    self.push(&mut res, Token::Imod, start);
                 }
                 ',' => {
                     self.consume_char();
-                    res.push(Token::Comma);
+                    self.push(&mut res, Token::Comma, start);
                 }
-                _ => {
+                '=' | '!' | '<' | '>' => {
+                    let op = self.consume_while(|c| c == '=' || c == '!' || c == '<' || c == '>');
+                    match self.parse_cmp(&op) {
+                        Ok(t) => self.push(&mut res, t, start),
+                        Err(e) => self.error(e, start),
+                    }
+                }
+                c if c.is_alphanumeric() || c == '.' => {
                     let expr = self.get_expr();
-                    res.push(self.parse_expr(&expr));
+                    let is_time = !self.eof() && self.next_char() == ':' &&
+                        expr.chars().all(|c| c.is_ascii_digit());
+                    let t = if is_time {
+                        self.get_time(&expr)
+                    } else {
+                        self.parse_expr(&expr)
+                    };
+                    match t {
+                        Ok(t) => self.push(&mut res, t, start),
+                        Err(e) => self.error(e, start),
+                    }
+                }
+                c => {
+                    self.consume_char();
+                    self.error(format!("unexpected character {:?}", c), start);
                 }
             }
+            self.consume_whitespace();
         }
         res
     }
@@ -221,10 +293,60 @@ impl Lexer {
 mod tests {
     use super::*;
 
+    fn tokens(v: Vec<Spanned<Token>>) -> Vec<Token> {
+        v.into_iter().map(|t| t.node).collect()
+    }
+
     #[test]
     fn test_lexer_simple() {
         let mut lexer = Lexer::new("ss");
-        assert_eq!(vec![Token::SS], lexer.lex());
+        assert_eq!(vec![Token::SS], tokens(lexer.lex()));
+    }
+    #[test]
+    fn test_errors() {
+        let errs = |s: &str| lex(s).err().unwrap_or_default();
+        assert_eq!(vec![KilaError::Lex("empty formula".into(), Span::new(0, 2))], errs("  "));
+        assert_eq!(
+            vec![KilaError::Lex("malformed number 1.2.3".into(), Span::new(2, 7))],
+            errs("5+1.2.3")
+        );
+        assert_eq!(
+            vec![
+                KilaError::Lex("unexpected character ';'".into(), Span::new(1, 2)),
+                KilaError::Lex("unexpected character '#'".into(), Span::new(3, 4)),
+            ],
+            errs("a; #b")
+        );
+        assert_eq!(
+            vec![KilaError::Lex("unknown operator =".into(), Span::new(1, 2))],
+            errs("a=b")
+        );
+    }
+    #[test]
+    fn test_time() {
+        let res = vec![Token::Time(45000.0), Token::Sub, Token::Time(45296.0)];
+        assert_eq!(res, tokens(lex("12:30 - 12:34:56").unwrap()));
+        assert_eq!(
+            Err(vec![KilaError::Lex("malformed time 12:75".into(), Span::new(0, 5))]),
+            lex("12:75")
+        );
+    }
+    #[test]
+    fn test_comparison() {
+        let res = vec![Token::Expr("a".into()), Token::Ge, Token::Num(5.0)];
+        assert_eq!(res, tokens(lex("a>=5 ").unwrap()));
+    }
+    #[test]
+    fn test_spans() {
+        let res = vec![
+            Spanned::new(Token::Min, Span::new(0, 3)),
+            Spanned::new(Token::ParL, Span::new(3, 4)),
+            Spanned::new(Token::Expr(".a".into()), Span::new(4, 6)),
+            Spanned::new(Token::Comma, Span::new(6, 7)),
+            Spanned::new(Token::Num(12.5), Span::new(8, 12)),
+            Spanned::new(Token::ParR, Span::new(12, 13)),
+        ];
+        assert_eq!(Ok(res), lex("min(.a, 12.5)"));
     }
     #[test]
     fn test_function_kipa_interpolate() {
@@ -238,7 +360,7 @@ mod tests {
             Token::Max,
             Token::ParL,
             Token::List,
-            Token::ParL,
+            Token::BrackL,
             Token::ParL,
             Token::Expr(String::from("a")),
             Token::Sub,
@@ -257,7 +379,7 @@ mod tests {
             Token::Num(0.0),
             Token::ParR,
             Token::ParR,
-            Token::ParR,
+            Token::BrackR,
             Token::ParR,
             Token::Comma,
             Token::Max,
@@ -289,6 +411,6 @@ mod tests {
             Token::ParR,
         ];
         let mut lexer = Lexer::new(inp);
-        assert_eq!(res, lexer.lex());
+        assert_eq!(res, tokens(lexer.lex()));
     }
 }
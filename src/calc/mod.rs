@@ -5,24 +5,20 @@
 pub mod kilac;
 pub mod lexer;
 pub mod parser;
+pub mod check;
 pub mod ctx;
+pub mod error;
+pub mod getter;
+pub mod span;
+pub mod value;
 
+use self::error::KilaError;
 use self::lexer::lex;
-use self::parser::{Fun, Ast, parse};
+use self::parser::{Fun, Ast, Signature, parse};
+use self::span::Span;
+pub use self::value::Value;
 use super::kipac;
 
-/// Internal macro for dealing with conditionals
-macro_rules! cond {
-    ($e:expr) => (
-        if $e {
-            1.0
-        } else {
-            0.0
-        };
-    )
-}
-
-
 /// Internal macro to make writing return types in eval easier
 macro_rules! value {
     ($i:ident, $e:expr) => (
@@ -32,135 +28,254 @@ macro_rules! value {
     )
 }
 
-fn ast_to_value(a: Ast) -> Result<Value, String> {
-    match a.clone() {
-        Ast::Leaf(n) => Ok(Value::Num(n)),
-        Ast::Node(v, f) => {
-            match f {
-                Fun::List => {
-                    let mut dv: Vec<f64> = Vec::new();
-                    for i in v.clone() {
-                        match i {
-                            Ast::Leaf(n) => dv.push(n),
-                            _ => return Err(format!("{:#?} is not a leaf in Node {:#?}", v, a))
-                        }
-                    }
-                    Ok(Value::Vec(dv))
-                }
-                _ => Err(format!("Function {:#?} is not a list in node {:#?}", f, a))
-            }
-        }
-        _ => Err(format!("Node {:#?} is not a leaf", a))
+/// Calculate points based on a single string and context information.
+/// Returns `None` if the formula has no value because of missing inputs.
+/// Errors point at the part of `s` that caused them. If `s` can not be
+/// lexed, every problem is merged into one error, see `KilaError::merge`.
+pub fn calculate_err<C: ctx::KilaCtx>(s: String, c: C) -> Result<Option<f64>, KilaError> {
+    let lexed = lex(&s).map_err(KilaError::merge)?;
+    let parsed = parse(lexed, c.clone())?;
+    let span = parsed.span();
+    match eval(parsed, c)? {
+        Value::Empty => Ok(None),
+        Value::List(_) => Err(KilaError::TypeMismatch {
+            expected: "number",
+            found: "list",
+            span,
+        }),
+        v => Ok(v.as_num()),
     }
 }
 
-// TODO: Drop this
-/// Value returned by the eval function.
-#[derive(Debug, Clone)]
-pub enum Value {
-    Num(f64),
-    Vec(Vec<f64>),
-}
-
-/// Calculate points based on a single string and context information
-pub fn calculate_err<C: ctx::KilaCtx>(s: String, c: C) -> Result<f64, String> {
-    let parsed = try!(parse(lex(&s), c.clone()));
-    return match eval(parsed, c) {
-        Ok(p) => {
-            match p {
-                Value::Num(n) => Ok(n),
-                Value::Vec(n) => Err(format!("Got Vector instead of number: {:#?}", n)),
-            }
-        }
-        Err(p) => Err(p),
-    };
-}
-
 /// Evaluate a string. Passes an EmptyCtx to the functions.
+/// Panics with the error message if the string can not be calculated,
+/// use `calculate_err` to handle errors. Returns NaN if the formula has
+/// no value.
 pub fn calculate(s: String) -> f64 {
-    let parsed = parse(lex(&s), ctx::EmptyCtx).ok().unwrap();
-    return match eval(parsed, self::ctx::EmptyCtx).ok().unwrap() {
-        Value::Num(n) => n,
-        Value::Vec(n) => panic!("Got Vector instead of number: {:#?}", n),
-    };
+    calculate_err(s, ctx::EmptyCtx)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .unwrap_or(f64::NAN)
 }
 
 /// A recursive evaluating function. This calculates the final value,
 /// whatever it is a list or something else from the AST supplied and context
-/// information. Do note that on hitting empty context or no context information,
-/// returns an error.
-pub fn eval<C: self::ctx::KilaCtx>(ast: Ast, c: C) -> Result<Value, String> {
-    return match ast {
-        Ast::Empty => panic!("Met empty abstract syntax tree node {:?}", ast),
-        Ast::Leaf(num) => value!(Num, num),
-        Ast::Node(vec, fun) => {
-            let mut res: Vec<f64> = Vec::new();
+/// information. Errors point at the offending node.
+pub fn eval<C: self::ctx::KilaCtx>(ast: Ast, c: C) -> Result<Value, KilaError> {
+    match ast {
+        Ast::Empty(_) => Ok(Value::Empty),
+        Ast::Leaf(v, _) => Ok(v),
+        Ast::Node(vec, fun, span) => {
+            fun.signature().check(fun, vec.len(), span)?;
+            let mut args = Vec::with_capacity(vec.len());
+            let mut spans = Vec::with_capacity(vec.len());
             for i in vec {
-                match try!(eval(i.clone(), c.clone())) {
-                    Value::Num(n) => res.push(n),
-                    Value::Vec(v) => {
-                        res = v.clone();
-                        break;
-                    }
-                }
+                spans.push(i.span());
+                args.push(eval(i, c.clone())?);
             }
-            match fun {
-                Fun::Abs => value!(Num, kipac::abs(res[0])),
-                Fun::Log => value!(Num, kipac::log(res[0])),
-                Fun::Aikavali => value!(Num, kipac::aikavali(res[0], res[1])),
-                Fun::Ln => value!(Num, kipac::ln(res[0])),
-                Fun::Floor => value!(Num, kipac::floor(res[0])),
-                Fun::Ceil => value!(Num, kipac::ceil(res[0])),
-                Fun::Sqrt => value!(Num, kipac::sqrt(res[0])),
-                Fun::Exp => value!(Num, kipac::exp(res[0])),
-                Fun::Pow => value!(Num, kipac::pow(res[0], res[1])),
-                Fun::Interpoloi => {
-                    value!(Num, kipac::interpoloi(res[0], res[1], res[2], res[3], 0.0))
-                }
-                Fun::Aikainterp => {
-                    value!(
-                        Num,
-                        kipac::interpoloi(res[0], res[1], res[1] + res[2], res[2], 0.0)
-                    )
-                }
-                Fun::Min => value!(Num, kipac::min(res)),
-                Fun::Max => value!(Num, kipac::max(res)),
-                Fun::Sum | Fun::Add => value!(Num, kipac::sum(res)),
-                Fun::Med => value!(Num, kipac::median(res)),
-                Fun::Kesk => value!(Num, kipac::mean(res)),
-                Fun::Logb => value!(Num, kipac::ln(res[1]) / kipac::ln(res[0])),
-                Fun::Div => value!(Num, res[0] / res[1]),
-                Fun::Mul => value!(Num, res[0] * res[1]),
-                Fun::Sub => value!(Num, res[0] - res[1]),
-                Fun::Mod => value!(Num, res[0] % res[1]),
-                Fun::Minus => value!(Num, -1.0 * res[0]),
-                Fun::Plus => value!(Num, res[0]),
-                Fun::Eq => value!(Num, cond!(res[0] == res[1])),
-                Fun::Neq => value!(Num, cond!(res[0] != res[1])),
-                Fun::Ge => value!(Num, cond!(res[0] <= res[1])),
-                Fun::Gt => value!(Num, cond!(res[0] < res[1])),
-                Fun::Le => value!(Num, cond!(res[0] >= res[1])),
-                Fun::Lt => value!(Num, cond!(res[0] > res[1])),
-                Fun::If => value!(Num, res.clone()[res[0] as usize + 1]),
-                Fun::Sin => value!(Num, f64::sin(res[0])),
-                Fun::Cos => value!(Num, f64::cos(res[0])),
-                Fun::Tan => value!(Num, f64::tan(res[0])),
-                Fun::Arcsin => value!(Num, f64::asin(res[0])),
-                Fun::Arccos => value!(Num, f64::acos(res[0])),
-                Fun::Arctan => value!(Num, f64::atan(res[0])),
-                Fun::List => value!(Vec, res),
-                _ => Err(format!("Function {:#?}", fun)),
+            apply(fun, args, &spans, span)
+        }
+        Ast::Get(g, span) => c.get(&g).map_err(|e| e.at(span)),
+    }
+}
+
+/// Applies `fun` to evaluated arguments.
+fn apply(fun: Fun, args: Vec<Value>, spans: &[Span], span: Span) -> Result<Value, KilaError> {
+    if let Signature::Variadic(_) = fun.signature() {
+        return match fun {
+            Fun::List => Ok(Value::List(args)),
+            Fun::Min | Fun::Max | Fun::Sum | Fun::Med | Fun::Kesk => aggregate(fun, args),
+            Fun::Interp | Fun::SS => ranked(fun, args, spans),
+            _ => Err(KilaError::UnknownFunction(fun, span)),
+        };
+    }
+    let broadcasts = matches!(
+        fun,
+        Fun::Add | Fun::Sub | Fun::Mul | Fun::Div | Fun::Mod | Fun::Pow |
+        Fun::Eq | Fun::Neq | Fun::Lt | Fun::Le | Fun::Gt | Fun::Ge
+    );
+    if broadcasts {
+        let mut args = args.into_iter();
+        let a = args.next().unwrap_or(Value::Empty);
+        let b = args.next().unwrap_or(Value::Empty);
+        return Value::binary(fun, a, b, span);
+    }
+    for (a, sp) in args.iter().zip(spans) {
+        if let Value::List(_) = *a {
+            return Err(KilaError::TypeMismatch {
+                expected: "number",
+                found: "list",
+                span: *sp,
+            });
+        }
+    }
+    if fun == Fun::If {
+        let mut args = args.into_iter();
+        let cond = args.next().unwrap_or(Value::Empty);
+        let (t, f) = (args.next(), args.next());
+        return Ok(match cond {
+            Value::Empty => Value::Empty,
+            ref c if c.truthy() => t.unwrap_or(Value::Empty),
+            _ => f.unwrap_or(Value::Empty),
+        });
+    }
+    if args.iter().any(Value::is_empty) {
+        return Ok(Value::Empty);
+    }
+    let res: Vec<f64> = args.iter().filter_map(Value::as_num).collect();
+    match fun {
+        Fun::Abs => value!(Num, kipac::abs(res[0])),
+        Fun::Log => value!(Num, kipac::log(res[0])),
+        Fun::Aikavali => {
+            let d = kipac::aikavali(res[0], res[1]);
+            match (&args[0], &args[1]) {
+                (&Value::Time(_), &Value::Time(_)) => value!(Duration, d),
+                _ => value!(Num, d),
             }
         }
-        Ast::Get(s) => {
-            ast_to_value(try!(c.get(s)))
+        Fun::Ln => value!(Num, kipac::ln(res[0])),
+        Fun::Floor => value!(Num, kipac::floor(res[0])),
+        Fun::Ceil => value!(Num, kipac::ceil(res[0])),
+        Fun::Sqrt => value!(Num, kipac::sqrt(res[0])),
+        Fun::Exp => value!(Num, kipac::exp(res[0])),
+        Fun::Interpoloi => {
+            let y2 = res.get(4).cloned().unwrap_or(0.0);
+            value!(Num, kipac::interpoloi(res[0], res[1], res[2], res[3], y2))
+        }
+        Fun::Aikainterp => {
+            value!(
+                Num,
+                kipac::interpoloi(res[0], res[1], res[1] + res[2], res[2], 0.0)
+            )
+        }
+        Fun::Logb => value!(Num, kipac::ln(res[1]) / kipac::ln(res[0])),
+        Fun::Minus => match args[0] {
+            Value::Duration(d) => value!(Duration, -d),
+            _ => value!(Num, -res[0]),
         },
+        Fun::Plus => Ok(args[0].clone()),
+        Fun::Pair => Ok(Value::List(args)),
+        Fun::Sin => value!(Num, f64::sin(res[0])),
+        Fun::Cos => value!(Num, f64::cos(res[0])),
+        Fun::Tan => value!(Num, f64::tan(res[0])),
+        Fun::Arcsin => value!(Num, f64::asin(res[0])),
+        Fun::Arccos => value!(Num, f64::acos(res[0])),
+        Fun::Arctan => value!(Num, f64::atan(res[0])),
+        _ => Err(KilaError::UnknownFunction(fun, span)),
+    }
+}
+
+/// `interp` and `ss`, whose first argument is the value looked up from the
+/// rest: points given as pairs for `interp`, values to rank against for `ss`.
+fn ranked(fun: Fun, mut args: Vec<Value>, spans: &[Span]) -> Result<Value, KilaError> {
+    let x = match args.remove(0) {
+        Value::Empty => return Ok(Value::Empty),
+        Value::List(_) => {
+            return Err(KilaError::TypeMismatch {
+                expected: "number",
+                found: "list",
+                span: spans[0],
+            })
+        }
+        x => x.as_num().unwrap_or(f64::NAN),
     };
+    if fun == Fun::SS {
+        let mut xs = Vec::new();
+        for a in args {
+            match a {
+                Value::List(v) => xs.extend(v.iter().map(Value::as_num)),
+                v => xs.push(v.as_num()),
+            }
+        }
+        return value!(Num, kilac::ss(x, xs));
+    }
+    let mut points = Vec::with_capacity(args.len());
+    for (a, sp) in args.into_iter().zip(&spans[1..]) {
+        match a {
+            Value::Empty => return Ok(Value::Empty),
+            Value::List(ref v) if v.len() == 2 => match (v[0].as_num(), v[1].as_num()) {
+                (Some(px), Some(py)) => points.push((px, py)),
+                _ => return Ok(Value::Empty),
+            },
+            a => {
+                return Err(KilaError::TypeMismatch {
+                    expected: "pair",
+                    found: a.kind(),
+                    span: *sp,
+                })
+            }
+        }
+    }
+    value!(Num, kilac::interp(x, points))
+}
+
+/// Aggregate over all arguments, lists are flattened. Missing inputs are
+/// skipped by the kipac aggregates and the result is empty only if nothing
+/// is left. The result has the type of the inputs if they all are of the
+/// same type.
+fn aggregate(fun: Fun, args: Vec<Value>) -> Result<Value, KilaError> {
+    let mut vals: Vec<Value> = Vec::new();
+    for a in args {
+        match a {
+            Value::List(v) => vals.extend(v),
+            v => vals.push(v),
+        }
+    }
+    let nums: Vec<Option<f64>> = vals.iter().map(Value::as_num).collect();
+    let n = match fun {
+        Fun::Min => kipac::min(nums),
+        Fun::Max => kipac::max(nums),
+        Fun::Sum => kipac::sum(nums),
+        Fun::Med => kipac::median(nums),
+        _ => kipac::mean(nums),
+    };
+    vals.retain(|v| !v.is_empty());
+    let n = match n {
+        Some(n) => n,
+        None => return Ok(Value::Empty),
+    };
+    let kind = vals[0].kind();
+    Ok(if vals.iter().all(|v| v.kind() == kind) {
+        match vals[0] {
+            Value::Time(_) => Value::Time(n),
+            Value::Duration(_) => Value::Duration(n),
+            _ => Value::Num(n),
+        }
+    } else {
+        Value::Num(n)
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    /// Context of a series of five teams seen from team 1. Team 3 did not
+    /// return input `a` and team 5 does not take part in the competition.
+    #[derive(Clone)]
+    struct SeriesCtx(Rc<HashMap<String, Value>>);
+
+    impl ctx::KilaCtx for SeriesCtx {
+        fn get(&self, g: &getter::Getter) -> Result<Value, KilaError> {
+            let g = g.to_string();
+            self.0.get(&g).cloned().ok_or_else(|| KilaError::UnknownIdentifier(g, Span::default()))
+        }
+    }
+
+    fn series() -> SeriesCtx {
+        let list = |v: Vec<Option<f64>>| {
+            Value::List(v.into_iter().map(|n| n.map(Value::Num).unwrap_or(Value::Empty)).collect())
+        };
+        let mut m = HashMap::new();
+        m.insert("a".into(), Value::Num(12.0));
+        m.insert("b".into(), Value::Empty);
+        m.insert(".a".into(), list(vec![Some(12.0), Some(8.0), None, Some(20.0), Some(100.0)]));
+        m.insert("muk.a".into(), list(vec![Some(12.0), Some(8.0), None, Some(20.0), None]));
+        let muk = list(vec![Some(1.0), Some(1.0), Some(1.0), Some(1.0), None]);
+        m.insert("muk".into(), muk);
+        SeriesCtx(Rc::new(m))
+    }
 
     #[test]
     fn test_add() {
@@ -206,6 +321,114 @@ mod tests {
         assert_eq!(-10.0, calculate("min(5, -10, 2)".into()));
     }
     #[test]
+    fn test_errors() {
+        let e = calculate_err("1 + 5/(2-2)".into(), ctx::EmptyCtx);
+        assert_eq!(Err(201), e.map_err(|e| e.code()));
+        let e = calculate_err("[1, 2]".into(), ctx::EmptyCtx);
+        assert_eq!(Err(200), e.map_err(|e| e.code()));
+        let e = calculate_err("2 * ; 3".into(), ctx::EmptyCtx);
+        assert_eq!(Err(100), e.map_err(|e| e.code()));
+        let e = calculate_err("1 + 5/(2-2)".into(), ctx::EmptyCtx);
+        assert_eq!(Err(Span::new(4, 11)), e.map_err(|e| e.span()));
+    }
+    #[test]
+    fn test_empty() {
+        assert_eq!(Ok(None), calculate_err("2 * a".into(), ctx::EmptyCtx));
+        assert_eq!(Ok(None), calculate_err("if(a > 1, 2, 3)".into(), ctx::EmptyCtx));
+        assert_eq!(Ok(Some(2.0)), calculate_err("if(1 > 0, 2, a)".into(), ctx::EmptyCtx));
+        assert_eq!(Ok(Some(3.0)), calculate_err("max(a, 3, [a, 1])".into(), ctx::EmptyCtx));
+        assert_eq!(Ok(None), calculate_err("max(a, [a])".into(), ctx::EmptyCtx));
+    }
+    #[test]
+    fn test_comparison() {
+        assert_eq!(1.0, calculate("3 >= 2".into()));
+        assert_eq!(0.0, calculate("3 <= 2".into()));
+        assert_eq!(1.0, calculate("3 > 2".into()));
+        assert_eq!(0.0, calculate("3 < 2".into()));
+        assert_eq!(5.0, calculate("(2 < 3) + 4".into()));
+    }
+    #[test]
+    fn test_time() {
+        assert_eq!(1800.0, calculate("12:30 - 12:00".into()));
+        assert_eq!(3600.0, calculate("aikavali(23:30, 0:30)".into()));
+        assert_eq!(30.0, calculate("(12:30 - 12:00) / 60".into()));
+        assert_eq!(Value::Time(45000.0), eval(Ast::Leaf(Value::Time(45000.0), Span::default()), ctx::EmptyCtx).unwrap());
+    }
+    #[test]
+    fn test_vector_argument() {
+        let e = calculate_err("abs([1, 2])".into(), ctx::EmptyCtx);
+        assert_eq!(Err(200), e.map_err(|e| e.code()));
+        assert_eq!(3.0, calculate("max([1, 3, 2])".into()));
+    }
+    #[test]
+    fn test_if() {
+        assert_eq!(2.0, calculate("if(1 == 1, 2, 3)".into()));
+        assert_eq!(3.0, calculate("if(1 != 1, 2, 3)".into()));
+        assert_eq!(2.0, calculate("if(5, 2, 3)".into()));
+    }
+    #[test]
+    fn test_interpoloi() {
+        assert_eq!(5.0, calculate("interpoloi(75, 100, 10, 50)".into()));
+        assert_eq!(6.0, calculate("interpoloi(75, 100, 10, 50, 2)".into()));
+        assert_eq!(10.0, calculate("interpoloi(150, 100, 10, 50)".into()));
+        assert_eq!(2.0, calculate("interpoloi(0, 100, 10, 50, 2)".into()));
+        assert_eq!(Ok(None), calculate_err("interpoloi(1, 0, 1, 2, a)".into(), ctx::EmptyCtx));
+    }
+    #[test]
+    fn test_interp() {
+        assert_eq!(2.5, calculate("interp(5, (0, 0), (10, 5), (20, 6))".into()));
+        assert_eq!(6.0, calculate("interp(25, (20, 6), (0, 0), (10, 5))".into()));
+        assert_eq!(Ok(None), calculate_err("interp(5, (0, a), (10, 5))".into(), ctx::EmptyCtx));
+        let e = calculate_err("interp(5, 1, (10, 5))".into(), ctx::EmptyCtx);
+        assert_eq!(Err(KilaError::TypeMismatch {
+            expected: "pair",
+            found: "number",
+            span: Span::new(10, 11),
+        }), e);
+        let e = calculate_err("(1, 2)".into(), ctx::EmptyCtx);
+        assert_eq!(Err(200), e.map_err(|e| e.code()));
+        // Every lexing error is kept.
+        let e = calculate_err("1 ; 2 # 3".into(), ctx::EmptyCtx).unwrap_err();
+        assert_eq!(
+            KilaError::Lex("unexpected character ';'; unexpected character '#'".into(), Span::new(2, 7)),
+            e
+        );
+    }
+    #[test]
+    fn test_ss() {
+        assert_eq!(2.0, calculate("ss(5, [1, 7, 5, 3])".into()));
+        assert_eq!(1.0, calculate("ss(-1, 0-[1, 7, 5, 3])".into()));
+        assert_eq!(Ok(Some(3.0)), calculate_err("ss(a, .a)".into(), series()));
+        assert_eq!(Ok(Some(2.0)), calculate_err("ss(a, .a*muk)".into(), series()));
+        assert_eq!(Ok(None), calculate_err("ss(b, .a)".into(), series()));
+    }
+    #[test]
+    fn test_kipa_regression() {
+        let cases: Vec<(&str, Option<f64>)> = vec![
+            ("min(.a)", Some(8.0)),
+            ("max(.a*muk)", Some(20.0)),
+            ("max(muk*.a)", Some(20.0)),
+            ("max(.a*..mukana)", Some(20.0)),
+            ("med(.a*..mukana)", Some(12.0)),
+            ("kesk(.a)", Some(35.0)),
+            ("kesk(.a*muk)", Some(40.0 / 3.0)),
+            ("a/max(.a*muk)*10", Some(6.0)),
+            ("sum(.a*..mukana-0)", Some(40.0)),
+            ("b*2", None),
+            ("if(b > 0, 5, 0)", None),
+            ("max(0, b)", Some(0.0)),
+            ("sum(.a*0+b)", None),
+            (
+                "max(interpoloi(max([(a-0),0.5*med((.a*..mukana-0))]),
+                max((.a*..mukana-0)),5,0.5*med((.a*..mukana-0))))",
+                Some(5.0 * (12.0 - 6.0) / (20.0 - 6.0)),
+            ),
+        ];
+        for (f, res) in cases {
+            assert_eq!(Ok(res), calculate_err(f.into(), series()), "{}", f);
+        }
+    }
+    #[test]
     fn unary() {
         assert_eq!(-10.0, calculate("-5*2".into()));
     }
@@ -1,90 +1,112 @@
 //! Module which consists all predefined applicators for parsing function.
 use super::super::ctx::*;
+use super::super::getter::Getter;
 use super::super::{eval, Value};
+use super::super::span::Span;
 use super::*;
 
-fn has_get(mut n: Vec<Ast>) -> bool {
-    while let Some(t) = n.pop() {
-        match t {
-            Ast::Get(_) => return true,
-            _ => (),
-        }
-    }
-    false
+fn has_get(n: Vec<Ast>) -> bool {
+    n.into_iter().any(is_get)
 }
 
 fn is_get(n: Ast) -> bool {
-    match n {
-        Ast::Get(_) => return true,
-        _ => false,
-    }
+    matches!(n, Ast::Get(..))
 }
 
 /// Tries to optimize a node by running it through evaluator. This applicator
 /// is used by default.
-pub fn optimize<C: KilaCtx>(nodes: Vec<Ast>, fun: Fun, c: C) -> Ast {
-    return match eval(Ast::Node(nodes.clone(), fun), c) {
-        Ok(Value::Num(n)) => Ast::Leaf(n),
-        _ => Ast::Node(nodes, fun),
-    };
+/// Only folds nodes that evaluate to a single, non-empty value.
+pub fn optimize<C: KilaCtx>(nodes: Vec<Ast>, fun: Fun, span: Span, c: C) -> Ast {
+    match eval(Ast::Node(nodes.clone(), fun, span), c) {
+        Ok(Value::List(_)) | Ok(Value::Empty) | Err(_) => Ast::Node(nodes, fun, span),
+        Ok(v) => Ast::Leaf(v, span),
+    }
 }
 
 /// This function fixes the kipas weird way of using multiplication operation
-/// in ..mux*a getter.
-pub fn fix_mulget(mut nodes: Vec<Ast>, fun: Fun) -> Option<Ast> {
-    return if fun == Fun::Mul && has_get(nodes.clone()) {
+/// in ..mux*a getter. In Kipa `.a*muk` and `.a*..mukana` are not
+/// multiplications but filters leaving only the teams that take part in the
+/// competition, so they are turned into the getter `.a` with the
+/// `participating` flag set, written `muk.a`.
+pub fn fix_mulget(mut nodes: Vec<Ast>, fun: Fun, span: Span) -> Option<Ast> {
+    if fun == Fun::Mul && has_get(nodes.clone()) {
         let a = nodes.pop()?;
         let b = nodes.pop()?;
-        if a == Ast::Get("muk".into()) && is_get(b.clone()) ||
-            b == Ast::Get("muk".into()) && is_get(a.clone())
-        {
-            Some(Ast::Get(format!(
-                "muk{}",
-                match b {
-                    Ast::Get(n) => n,
-                    _ => panic!("Error"),
-                }
-            )))
+        let is_muk = |n: &Ast| matches!(*n, Ast::Get(Getter::Participation, _));
+        let other = if is_muk(&a) && is_get(b.clone()) {
+            b
+        } else if is_muk(&b) && is_get(a.clone()) {
+            a
         } else {
-            None
+            return None;
+        };
+        match other {
+            Ast::Get(Getter::Input(mut p), _) => {
+                p.participating = true;
+                Some(Ast::Get(Getter::Input(p), span))
+            }
+            _ => None,
         }
     } else {
         None
-    };
+    }
 }
 
 /// Empty applicator. Does really nothing
-pub fn empty<C: KilaCtx>(nodes: Vec<Ast>, fun: Fun, _: C) -> Ast {
-    Ast::Node(nodes, fun)
+pub fn empty<C: KilaCtx>(nodes: Vec<Ast>, fun: Fun, span: Span, _: C) -> Ast {
+    Ast::Node(nodes, fun, span)
+}
+
+/// Applicator for checking formulas. Only turns `.a*muk` into `muk.a`,
+/// nothing is evaluated.
+pub fn filter<C: KilaCtx>(nodes: Vec<Ast>, fun: Fun, span: Span, _: C) -> Ast {
+    fix_mulget(nodes.clone(), fun, span).unwrap_or(Ast::Node(nodes, fun, span))
 }
 
-/// The default applicator. This should be the only one to be used.
-pub fn basic<C: KilaCtx>(nodes: Vec<Ast>, fun: Fun, c: C) -> Ast {
-    let t = fix_mulget(nodes.clone(), fun);
-    return match t {
+/// The default applicator, used for calculating.
+pub fn basic<C: KilaCtx>(nodes: Vec<Ast>, fun: Fun, span: Span, c: C) -> Ast {
+    let t = fix_mulget(nodes.clone(), fun, span);
+    match t {
         Some(n) => {
             match n {
-                Ast::Get(s) => {
-                    match c.get(s.clone()) {
-                        Ok(l) => l,
-                        _ => Ast::Get(s),
+                Ast::Get(g, sp) => {
+                    match c.get(&g) {
+                        Ok(v) => Ast::Leaf(v, sp),
+                        _ => Ast::Get(g, sp),
                     }
                 }
                 _ => n,
             }
         }
         None => {
-            optimize(nodes, fun, c)
+            optimize(nodes, fun, span, c)
         }
-    };
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_fix_mulget() {
+        let s = Span::default();
+        let get = |g: &str| Ast::Get(Getter::parse(g).unwrap(), s);
+        let res = Some(get("muk.a"));
+        assert_eq!(res, fix_mulget(vec![get(".a"), get("muk")], Fun::Mul, s));
+        assert_eq!(res, fix_mulget(vec![get("muk"), get(".a")], Fun::Mul, s));
+        assert_eq!(res, fix_mulget(vec![get(".a"), get("..mukana")], Fun::Mul, s));
+        assert_eq!(None, fix_mulget(vec![get(".a"), get("..mukana")], Fun::Add, s));
+        assert_eq!(None, fix_mulget(vec![get(".a"), get("b")], Fun::Mul, s));
+        assert_eq!(None, fix_mulget(vec![get("vartio"), get("muk")], Fun::Mul, s));
+    }
     #[test]
     fn test_optimize() {
-        assert_eq!(Ast::Leaf(12.0), optimize(vec![Ast::Leaf(5.0), Ast::Leaf(7.0)], Fun::Add, EmptyCtx));
+        let s = Span::new(0, 3);
+        let nodes = vec![
+            Ast::Leaf(Value::Num(5.0), Span::new(0, 1)),
+            Ast::Leaf(Value::Num(7.0), Span::new(2, 3)),
+        ];
+        assert_eq!(Ast::Leaf(Value::Num(12.0), s), optimize(nodes, Fun::Add, s, EmptyCtx));
     }
 }
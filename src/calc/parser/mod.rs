@@ -1,37 +1,62 @@
 //! This module hosts the parser, applicators and their implementations.
-//! The parser used is a Shunting-Yard based parser.
+//! The parser used is a Pratt (precedence climbing) parser, see
+//! doc/parser.md for the precedence table.
 pub mod applicators;
+use std::fmt;
+
+use super::error::KilaError;
+use super::getter::Getter;
 use super::lexer::Token;
+use super::span::{Span, Spanned};
+use super::value::Value;
 
-/// Handy macro for returning arity
-macro_rules! arity {
-    ($e:expr, $b:expr) => (
-        match $e {
-            Fun::Add | Fun::Sub | Fun::Div | Fun::Mul | Fun::Mod | Fun::Pow => 2,
-            Fun::Eq | Fun::Neq | Fun::Ge | Fun::Gt | Fun::Le | Fun::Lt => 2,
-            Fun::Aikavali | Fun::Logb => 2,
-            Fun::Log | Fun::Ln | Fun::Floor | Fun::Ceil | Fun::Sqrt | Fun::Exp => 1,
-            Fun::Sin | Fun::Cos | Fun::Tan | Fun::Arcsin | Fun::Arccos | Fun::Arctan => 1,
-            Fun::If | Fun::Aikainterp => 3,
-            Fun::Minus | Fun::Plus => 1,
-            _ => $b.pop().unwrap()
-        };
-    )
+/// Number of arguments a function accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signature {
+    /// Exactly this many.
+    Fixed(usize),
+    /// At least the first and at most the second, e.g. `interpoloi` whose
+    /// `y2` is optional.
+    Optional(usize, usize),
+    /// Any number but at least this many, e.g. aggregates like `min`.
+    Variadic(usize),
 }
 
-/// Easy way of building children internally
-macro_rules! children {
-    ($e:expr, $b:expr) => (
-        {
-            let mut v: Vec<Ast> = Vec::new();
-            for _ in 0..$e {
-                v.push($b.pop().unwrap())
-            }
-            v.iter().rev().cloned().collect::<Vec<Ast>>()
-        };
-    )
+impl Signature {
+    /// Whether `n` arguments are accepted.
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Signature::Fixed(a) => n == a,
+            Signature::Optional(a, b) => a <= n && n <= b,
+            Signature::Variadic(a) => a <= n,
+        }
+    }
+
+    /// Returns arity error pointing at `span` if `n` arguments are not
+    /// accepted by `fun`.
+    pub fn check(&self, fun: Fun, n: usize, span: Span) -> Result<(), KilaError> {
+        if self.accepts(n) {
+            Ok(())
+        } else {
+            Err(KilaError::Arity {
+                fun,
+                expected: *self,
+                found: n,
+                span,
+            })
+        }
+    }
 }
 
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Signature::Fixed(a) => write!(f, "{}", a),
+            Signature::Optional(a, b) => write!(f, "{} to {}", a, b),
+            Signature::Variadic(a) => write!(f, "at least {}", a),
+        }
+    }
+}
 
 /// Functions. These are used in the AST to signal which functions are at use.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
@@ -81,17 +106,169 @@ pub enum Fun {
 }
 
 /// AST structure itself. Currently no need for boxes or other fancy stuff.
+/// Every node carries the span of the formula it was built from.
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Ast {
-    Node(Vec<Ast>, Fun),
-    Leaf(f64),
-    Get(String),
-    Empty,
+    Node(Vec<Ast>, Fun, Span),
+    /// Constant, either written in the formula or folded by an applicator.
+    Leaf(Value, Span),
+    Get(Getter, Span),
+    Empty(Span),
+}
+
+impl Ast {
+    /// Span of the formula this node was built from.
+    pub fn span(&self) -> Span {
+        match *self {
+            Ast::Node(_, _, s) | Ast::Leaf(_, s) | Ast::Get(_, s) | Ast::Empty(s) => s,
+        }
+    }
+
+    /// Replaces the span of this node (not its children).
+    pub fn with_span(self, span: Span) -> Ast {
+        match self {
+            Ast::Node(v, f, _) => Ast::Node(v, f, span),
+            Ast::Leaf(n, _) => Ast::Leaf(n, span),
+            Ast::Get(g, _) => Ast::Get(g, span),
+            Ast::Empty(_) => Ast::Empty(span),
+        }
+    }
+}
+
+impl Fun {
+    /// Declared signature of the function. Parser and eval reject calls that
+    /// do not match it.
+    pub fn signature(&self) -> Signature {
+        match *self {
+            Fun::Add | Fun::Sub | Fun::Div | Fun::Mul | Fun::Mod | Fun::Pow => Signature::Fixed(2),
+            Fun::Eq | Fun::Neq | Fun::Ge | Fun::Gt | Fun::Le | Fun::Lt => Signature::Fixed(2),
+            Fun::Aikavali | Fun::Logb | Fun::Pair => Signature::Fixed(2),
+            Fun::Abs | Fun::Log | Fun::Ln | Fun::Floor | Fun::Ceil | Fun::Sqrt | Fun::Exp => {
+                Signature::Fixed(1)
+            }
+            Fun::Sin | Fun::Cos | Fun::Tan | Fun::Arcsin | Fun::Arccos | Fun::Arctan => {
+                Signature::Fixed(1)
+            }
+            Fun::Minus | Fun::Plus => Signature::Fixed(1),
+            Fun::If | Fun::Aikainterp => Signature::Fixed(3),
+            Fun::Interpoloi => Signature::Optional(4, 5),
+            Fun::Min | Fun::Max | Fun::Sum | Fun::Med | Fun::Kesk => Signature::Variadic(1),
+            Fun::Interp | Fun::SS => Signature::Variadic(2),
+            Fun::List | Fun::Empty => Signature::Variadic(0),
+        }
+    }
+}
+
+impl Fun {
+    /// Binding powers of the function written as an infix operator, see
+    /// `infix_bp`.
+    pub fn binding(&self) -> Option<(u8, u8)> {
+        match *self {
+            Fun::Eq | Fun::Neq | Fun::Lt | Fun::Le | Fun::Gt | Fun::Ge => Some((1, 2)),
+            Fun::Add | Fun::Sub => Some((3, 4)),
+            Fun::Mul | Fun::Div | Fun::Mod => Some((5, 6)),
+            Fun::Pow => Some((10, 9)),
+            _ => None,
+        }
+    }
+
+    /// How the function is written in formulas: the operator of operators,
+    /// otherwise the name of the function. Finnish aliases like `pienin`
+    /// are written with the English name.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Fun::Add | Fun::Plus => "+",
+            Fun::Sub | Fun::Minus => "-",
+            Fun::Mul => "*",
+            Fun::Div => "/",
+            Fun::Mod => "%",
+            Fun::Pow => "^",
+            Fun::Eq => "==",
+            Fun::Neq => "!=",
+            Fun::Lt => "<",
+            Fun::Le => "<=",
+            Fun::Gt => ">",
+            Fun::Ge => ">=",
+            Fun::Sin => "sin",
+            Fun::Cos => "cos",
+            Fun::Tan => "tan",
+            Fun::Arcsin => "arcsin",
+            Fun::Arccos => "arccos",
+            Fun::Arctan => "arctan",
+            Fun::Logb => "logb",
+            Fun::Interp => "interp",
+            Fun::Aikainterp => "aikainterp",
+            Fun::Aikavali => "aikavali",
+            Fun::Abs => "abs",
+            Fun::Log => "log",
+            Fun::Ln => "ln",
+            Fun::Floor => "floor",
+            Fun::Ceil => "ceil",
+            Fun::Sqrt => "sqrt",
+            Fun::Exp => "exp",
+            Fun::Interpoloi => "interpoloi",
+            Fun::Min => "min",
+            Fun::Max => "max",
+            Fun::Sum => "sum",
+            Fun::Med => "med",
+            Fun::Kesk => "kesk",
+            Fun::If => "if",
+            Fun::SS => "ss",
+            Fun::List | Fun::Pair | Fun::Empty => "",
+        }
+    }
+}
+
+/// Binding powers of the node when written out. Prefix operators never
+/// need parentheses on the right of an operator.
+fn node_bp(a: &Ast) -> Option<(u8, u8)> {
+    match *a {
+        Ast::Node(_, Fun::Minus, _) | Ast::Node(_, Fun::Plus, _) => Some((u8::MAX, PREFIX_BP)),
+        Ast::Node(ref v, fun, _) if v.len() == 2 => fun.binding(),
+        _ => None,
+    }
+}
+
+/// Writes `a`, in parentheses if `parens` says so of its binding powers.
+fn operand<P: Fn((u8, u8)) -> bool>(f: &mut fmt::Formatter, a: &Ast, parens: P) -> fmt::Result {
+    if node_bp(a).map(parens).unwrap_or(false) {
+        write!(f, "({})", a)
+    } else {
+        write!(f, "{}", a)
+    }
+}
+
+/// The formula in normal form: English function names, spaces around
+/// infix operators and only the parentheses needed. Parsing it gives the
+/// same tree, spans aside.
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (v, fun) = match *self {
+            Ast::Leaf(ref v, _) => return write!(f, "{}", v),
+            Ast::Get(ref g, _) => return write!(f, "{}", g),
+            Ast::Empty(_) => return write!(f, "-"),
+            Ast::Node(ref v, fun, _) => (v, fun),
+        };
+        if let (Some((l, r)), 2) = (fun.binding(), v.len()) {
+            operand(f, &v[0], |(_, cr)| l >= cr)?;
+            write!(f, " {} ", fun.name())?;
+            return operand(f, &v[1], |(cl, _)| cl < r);
+        }
+        if fun == Fun::Minus || fun == Fun::Plus {
+            write!(f, "{}", fun.name())?;
+            return operand(f, &v[0], |(cl, _)| cl < PREFIX_BP);
+        }
+        let args: Vec<String> = v.iter().map(|a| a.to_string()).collect();
+        match fun {
+            Fun::List => write!(f, "[{}]", args.join(", ")),
+            _ => write!(f, "{}({})", fun.name(), args.join(", ")),
+        }
+    }
 }
 
 impl From<Token> for Fun {
     fn from(token: Token) -> Self {
-        return match token {
+        match token {
             Token::Abs => Fun::Abs,
             Token::Add => Fun::Add,
             Token::Aikainterp => Fun::Aikainterp,
@@ -108,6 +285,7 @@ impl From<Token> for Fun {
             Token::Ge => Fun::Ge,
             Token::Gt => Fun::Gt,
             Token::If => Fun::If,
+            Token::Interp => Fun::Interp,
             Token::Imod | Token::Mod => Fun::Mod,
             Token::Interpoloi => Fun::Interpoloi,
             Token::Ipow | Token::Pow => Fun::Pow,
@@ -119,6 +297,7 @@ impl From<Token> for Fun {
             Token::Logb => Fun::Logb,
             Token::Lt => Fun::Lt,
             Token::Max => Fun::Max,
+            Token::Med => Fun::Med,
             Token::Min => Fun::Min,
             Token::Minus => Fun::Minus,
             Token::Mul => Fun::Mul,
@@ -129,142 +308,235 @@ impl From<Token> for Fun {
             Token::Sqrt => Fun::Sqrt,
             Token::Sub => Fun::Sub,
             Token::Sum => Fun::Sum,
-            Token::Tan => Fun::Cos,
+            Token::Tan => Fun::Tan,
             _ => Fun::Empty,
-        };
+        }
     }
 }
 
 /// Parser algorithm.
-pub fn parse<C: super::ctx::KilaCtx + Clone>(input: Vec<Token>, c: C) -> Result<Ast, String> {
+pub fn parse<C: super::ctx::KilaCtx + Clone>(
+    input: Vec<Spanned<Token>>,
+    c: C,
+) -> Result<Ast, KilaError> {
     parse_fn(input, applicators::basic, c)
 }
 
-/// We will use Shunting-Yard algorithm.
+/// Parses `input` calling `app` for every node built, from the leaves up.
+/// `app` decides what the node becomes, see applicators.
 pub fn parse_fn<F, C: super::ctx::KilaCtx + Clone>(
-    input: Vec<Token>,
+    input: Vec<Spanned<Token>>,
     app: F,
     ctx: C,
-) -> Result<Ast, String>
+) -> Result<Ast, KilaError>
 where
-    F: Fn(Vec<Ast>, Fun, C) -> Ast,
+    F: Fn(Vec<Ast>, Fun, Span, C) -> Ast,
 {
-    let mut prev: Vec<Token> = Vec::new();
-    let mut opr: Vec<Token> = Vec::new();
-    let mut node: Vec<Ast> = Vec::new();
-    let mut arity: Vec<usize> = Vec::new();
-    let mut iter = input.iter().cloned().peekable();
-    while let Some(t) = iter.next() {
-        match t.clone() {
-            Token::Num(n) => node.push(Ast::Leaf(n)),
-            Token::Expr(n) => node.push(Ast::Get(n)),
-            Token::Empty => return Err(format!("Got empty")),
-            Token::Comma => {
-                *arity.last_mut().unwrap() += 1;
-                while let Some(op) = opr.pop() {
-                    match op {
-                        Token::ParL => {
-                            opr.push(op.clone());
-                            break;
-                        }
-                        _ => {
-                            let fun = Fun::from(op);
-                            let ar = arity!(fun, arity);
-                            let nod = app(children!(ar, node), fun, ctx.clone());
-                            node.push(nod);
-                        }
-                    }
-                }
-            }
-            Token::ParL => opr.push(Token::ParL),
-            Token::ParR => {
-                while let Some(op) = opr.pop() {
-                    match op {
-                        Token::ParL => break,
-                        _ => {
-                            let fun = Fun::from(op);
-                            let ar = arity!(fun, arity);
-                            let nod = app(children!(ar, node), fun, ctx.clone());
-                            node.push(nod);
-                        }
-                    }
-                }
+    let mut p = Parser {
+        tokens: input,
+        pos: 0,
+        app,
+        ctx,
+    };
+    if p.tokens.is_empty() {
+        return Err(KilaError::Syntax("empty formula".into(), Span::default()));
+    }
+    let ast = p.expr(0)?;
+    match p.next() {
+        None => Ok(ast),
+        Some(t) => match t.node {
+            Token::ParR | Token::BrackR => Err(KilaError::UnbalancedParens(t.span)),
+            Token::Comma => Err(KilaError::Syntax("comma outside function call".into(), t.span)),
+            _ => Err(KilaError::Syntax(format!("unexpected {:?}", t.node), t.span)),
+        },
+    }
+}
+
+/// Binding power of prefix operators. Binds tighter than `*` but looser
+/// than `^`, so `-2^2` is `-(2^2)` and `-2*3` is `(-2)*3`.
+const PREFIX_BP: u8 = 7;
+
+/// Left and right binding powers of an infix operator. Left associative
+/// operators bind tighter on the right, right associative on the left.
+fn infix_bp(t: &Token) -> Option<(u8, u8)> {
+    match *t {
+        Token::Eq | Token::Neq | Token::Lt | Token::Le | Token::Gt | Token::Ge |
+        Token::Add | Token::Sub | Token::Mul | Token::Div | Token::Imod | Token::Ipow => {
+            Fun::from(t.clone()).binding()
+        }
+        _ => None,
+    }
+}
+
+struct Parser<F, C> {
+    tokens: Vec<Spanned<Token>>,
+    pos: usize,
+    app: F,
+    ctx: C,
+}
+
+impl<F, C> Parser<F, C>
+where
+    F: Fn(Vec<Ast>, Fun, Span, C) -> Ast,
+    C: super::ctx::KilaCtx + Clone,
+{
+    fn peek(&self) -> Option<&Spanned<Token>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Spanned<Token>> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    /// Span just past the last token, where a missing token would be.
+    fn end(&self) -> Span {
+        let e = self.tokens.last().map(|t| t.span.end).unwrap_or(0);
+        Span::new(e, e)
+    }
+
+    /// Checks the node against its signature and hands it to the applicator.
+    fn apply(&self, ch: Vec<Ast>, fun: Fun, span: Span) -> Result<Ast, KilaError> {
+        fun.signature().check(fun, ch.len(), span)?;
+        Ok((self.app)(ch, fun, span, self.ctx.clone()))
+    }
+
+    /// Parses an expression whose operators all bind tighter than `min_bp`.
+    fn expr(&mut self, min_bp: u8) -> Result<Ast, KilaError> {
+        let mut lhs = self.prefix()?;
+        while let Some(op) = self.peek().cloned() {
+            let (lbp, rbp) = match infix_bp(&op.node) {
+                Some(bp) => bp,
+                None => break,
+            };
+            if lbp < min_bp {
+                break;
             }
-            Token::Eq | Token::Neq | Token::Gt | Token::Ge | Token::Lt | Token::Le |
+            self.next();
+            let rhs = self.expr(rbp)?;
+            let span = lhs.span().to(rhs.span());
+            lhs = self.apply(vec![lhs, rhs], Fun::from(op.node), span)?;
+        }
+        Ok(lhs)
+    }
+
+    /// Parses an operand: a literal, getter, parenthesised expression,
+    /// function call or an operand with a prefix operator.
+    fn prefix(&mut self) -> Result<Ast, KilaError> {
+        let t = match self.next() {
+            Some(t) => t,
+            None => return Err(KilaError::Syntax("expected operand".into(), self.end())),
+        };
+        match t.node {
+            Token::Num(n) => Ok(Ast::Leaf(Value::Num(n), t.span)),
+            Token::Time(n) => Ok(Ast::Leaf(Value::Time(n), t.span)),
+            Token::Expr(ref n) => Getter::parse(n)
+                .map(|g| Ast::Get(g, t.span))
+                .map_err(|e| e.at(t.span)),
+            // `-` without an operand is the empty value, as in the normal form.
+            Token::Sub if self.peek().map(|n| ends_operand(&n.node)).unwrap_or(true) => Ok(Ast::Empty(t.span)),
             Token::Add | Token::Sub => {
-                match prev.last() {
-                    Some(pre) => {
-                        match pre.clone() {
-                            Token::ParR | Token::Num(_) | Token::Expr(_) => {
-                                while let Some(op) = opr.pop() {
-                                    match op {
-                                        Token::ParL => {
-                                            opr.push(Token::ParL);
-                                            break;
-                                        }
-                                        _ => {
-                                            let fun = Fun::from(op);
-                                            let ar = arity!(fun, arity);
-                                            let nod = app(children!(ar, node), fun, ctx.clone());
-                                            node.push(nod);
-                                        }
-                                    }
-                                }
-                                opr.push(t.clone());
-                            }
-                            _ => {
-                                opr.push(match t {
-                                    Token::Add => Token::Plus,
-                                    Token::Sub => Token::Minus,
-                                    _ => return Err(format!("Shouldn't happen. Unary operator")),
-                                })
+                let fun = if t.node == Token::Add { Fun::Plus } else { Fun::Minus };
+                let rhs = self.expr(PREFIX_BP)?;
+                let span = t.span.to(rhs.span());
+                self.apply(vec![rhs], fun, span)
+            }
+            Token::ParL => {
+                if self.peek().is_none() {
+                    return Err(KilaError::UnbalancedParens(t.span));
+                }
+                // `(a)` is a parenthesised expression, `(a, b)` a pair.
+                let mut items = vec![self.expr(0)?];
+                loop {
+                    match self.next() {
+                        Some(ref c) if c.node == Token::Comma => {
+                            if self.peek().is_none() {
+                                return Err(KilaError::UnbalancedParens(t.span));
                             }
+                            items.push(self.expr(0)?);
                         }
-                    }
-                    None => {
-                        opr.push(match t {
-                            Token::Add => Token::Plus,
-                            Token::Sub => Token::Minus,
-                            _ => return Err(format!("Shouldn't happen. Unary operator")),
-                        })
-                    }
-                }
-            }
-            Token::Mul | Token::Div | Token::Imod => {
-                while let Some(op) = opr.pop() {
-                    match op {
-                        Token::Add | Token::Sub | Token::ParL => {
-                            opr.push(op);
-                            break;
+                        Some(ref c) if c.node == Token::BrackR => {
+                            return Err(KilaError::UnbalancedParens(t.span.to(c.span)));
                         }
-                        _ => {
-                            let fun = Fun::from(op);
-                            let ar = arity!(fun, arity);
-                            let nod = app(children!(ar, node), fun, ctx.clone());
-                            node.push(nod);
+                        Some(ref c) if c.node == Token::ParR => {
+                            let span = t.span.to(c.span);
+                            if items.len() == 1 {
+                                return Ok(items.remove(0).with_span(span));
+                            }
+                            return self.apply(items, Fun::Pair, span);
                         }
+                        _ => return Err(KilaError::UnbalancedParens(t.span)),
                     }
                 }
-                opr.push(t.clone());
-            }
-            Token::Ipow => opr.push(Token::Ipow),
-            _ => {
-                opr.push(t.clone());
-                arity.push(1);
             }
+            ref f if is_function(f) => self.call(t.clone()),
+            ref f => Err(KilaError::Syntax(format!("expected operand, got {:?}", f), t.span)),
         }
-        prev.push(t.clone());
     }
-    while let Some(op) = opr.pop() {
-        let fun = Fun::from(op);
-        let ar = arity!(fun, arity);
-        let nod = app(children!(ar, node), fun, ctx.clone());
-        node.push(nod);
+
+    /// Parses the parenthesised argument list of function `f`, or the items
+    /// of a list `[...]`. The closing bracket must match the opening one.
+    fn call(&mut self, f: Spanned<Token>) -> Result<Ast, KilaError> {
+        let (open, close, other) = match self.next() {
+            Some(ref t) if t.node == Token::ParL => (t.span, Token::ParR, Token::BrackR),
+            Some(ref t) if t.node == Token::BrackL => (t.span, Token::BrackR, Token::ParR),
+            Some(t) => return Err(KilaError::Syntax(format!("expected ( after {:?}", f.node), t.span)),
+            None => return Err(KilaError::Syntax(format!("expected ( after {:?}", f.node), f.span)),
+        };
+        let mut args = Vec::new();
+        if self.peek().map(|t| t.node == other).unwrap_or(false) {
+            return Err(KilaError::UnbalancedParens(open.to(self.next().unwrap().span)));
+        }
+        if self.peek().map(|t| t.node == close).unwrap_or(false) {
+            let close = self.next().unwrap();
+            return self.apply(args, Fun::from(f.node), f.span.to(close.span));
+        }
+        loop {
+            if self.peek().is_none() {
+                return Err(KilaError::UnbalancedParens(open));
+            }
+            args.push(self.expr(0)?);
+            match self.next() {
+                Some(ref t) if t.node == Token::Comma => continue,
+                Some(ref t) if t.node == close => {
+                    let span = f.span.to(t.span);
+                    return self.apply(args, Fun::from(f.node), span);
+                }
+                Some(ref t) if t.node == other => return Err(KilaError::UnbalancedParens(open.to(t.span))),
+                Some(t) => return Err(KilaError::Syntax(format!("unexpected {:?}", t.node), t.span)),
+                None => return Err(KilaError::UnbalancedParens(open)),
+            }
+        }
     }
-    if node.len() > 1 {
-        return Err(format!("Too many members: {:#?}", node));
+}
+
+/// Checks every function in the tree against its signature. Trees built by
+/// the parser are always valid, this is for trees built by other means.
+pub fn validate(ast: &Ast) -> Result<(), KilaError> {
+    if let Ast::Node(ref v, fun, span) = *ast {
+        fun.signature().check(fun, v.len(), span)?;
+        for n in v {
+            validate(n)?;
+        }
     }
-    return Ok(node.pop().unwrap());
+    Ok(())
+}
+
+/// Whether the token ends an operand, so `-` before it has nothing to negate.
+fn ends_operand(t: &Token) -> bool {
+    matches!(*t, Token::Comma | Token::ParR | Token::BrackR)
+}
+
+/// Whether the token is a function called with parentheses.
+fn is_function(t: &Token) -> bool {
+    !matches!(
+        *t,
+        Token::ParL | Token::ParR | Token::Comma | Token::Num(_) | Token::Time(_) | Token::Expr(_) |
+        Token::Empty | Token::Add | Token::Sub | Token::Mul | Token::Div | Token::Imod |
+        Token::Ipow | Token::Plus | Token::Minus | Token::Eq | Token::Neq | Token::Lt |
+        Token::Le | Token::Gt | Token::Ge | Token::BrackL | Token::BrackR
+    )
 }
 
 #[cfg(test)]
@@ -274,23 +546,209 @@ mod tests {
     use super::*;
 
     macro_rules! node {
-        ($i:ident, $($e:expr),+) => (
-            Ast::Node(vec![$($e),+], Fun::$i)
+        ($i:ident, $s:expr, $($e:expr),+) => (
+            Ast::Node(vec![$($e),+], Fun::$i, Span::new($s.start, $s.end))
         )
     }
 
     macro_rules! leaf {
-        ($e:expr) => (
-            Ast::Leaf($e as f64)
+        ($e:expr, $s:expr) => (
+            Ast::Leaf(Value::Num($e as f64), Span::new($s.start, $s.end))
             )
     }
 
     fn parse_test(s: &str) -> Ast {
-        parse_fn(lex(s.into()), applicators::empty, EmptyCtx).ok().unwrap()
+        parse_fn(lex(s).unwrap(), applicators::empty, EmptyCtx).ok().unwrap()
+    }
+
+    /// Tree without spans as an s-expression, e.g. `(Add a (Mul b c))`.
+    fn sexp(a: &Ast) -> String {
+        match *a {
+            Ast::Node(ref v, f, _) => {
+                let ch: Vec<String> = v.iter().map(sexp).collect();
+                format!("({:?} {})", f, ch.join(" "))
+            }
+            Ast::Leaf(ref v, _) => v.to_string(),
+            Ast::Get(ref g, _) => g.to_string(),
+            Ast::Empty(_) => "_".into(),
+        }
+    }
+
+    /// Infix operators from loosest to tightest binding with their
+    /// associativity, `true` for left associative.
+    const LEVELS: &[(&[(&str, Fun)], bool)] = &[
+        (
+            &[
+                ("==", Fun::Eq),
+                ("!=", Fun::Neq),
+                ("<", Fun::Lt),
+                (">", Fun::Gt),
+                ("<=", Fun::Le),
+                (">=", Fun::Ge),
+            ],
+            true,
+        ),
+        (&[("+", Fun::Add), ("-", Fun::Sub)], true),
+        (&[("*", Fun::Mul), ("/", Fun::Div), ("%", Fun::Mod)], true),
+        (&[("^", Fun::Pow)], false),
+    ];
+
+    #[test]
+    fn test_operator_pairs() {
+        for (i, &(ops1, _)) in LEVELS.iter().enumerate() {
+            for &(o1, f1) in ops1 {
+                for (j, &(ops2, left)) in LEVELS.iter().enumerate() {
+                    for &(o2, f2) in ops2 {
+                        let inp = format!("a {} b {} c", o1, o2);
+                        let first = format!("({:?} ({:?} a b) c)", f2, f1);
+                        let second = format!("({:?} a ({:?} b c))", f1, f2);
+                        let res = if i > j || i == j && left { first } else { second };
+                        assert_eq!(res, sexp(&parse_test(&inp)), "{}", inp);
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_unary() {
+        assert_eq!("(Minus (Pow 2 2))", sexp(&parse_test("-2^2")));
+        assert_eq!("(Mul (Minus 2) 3)", sexp(&parse_test("-2*3")));
+        assert_eq!("(Pow 2 (Minus 3))", sexp(&parse_test("2^-3")));
+        assert_eq!("(Mul 2 (Minus 3))", sexp(&parse_test("2*-3")));
+        assert_eq!("(Sub 2 (Minus (Plus 3)))", sexp(&parse_test("2 - -+3")));
+        assert_eq!("(Gt (Minus a) (Add b 1))", sexp(&parse_test("-a > b + 1")));
+    }
+    #[test]
+    fn test_calls() {
+        assert_eq!("(Mod (Add 1 2) 3)", sexp(&parse_test("(1+2)%3")));
+        assert_eq!("(Mod 1 2)", sexp(&parse_test("mod(1, 2)")));
+        assert_eq!("(Pow (Min 1 2) 2)", sexp(&parse_test("min(1, 2)^2")));
+        assert_eq!("(List 1 (Add 2 3))", sexp(&parse_test("[1, 2+3]")));
+        assert_eq!(
+            "(Max (Mul .a muk) 0)",
+            sexp(&parse_test("max(.a*..mukana, 0)"))
+        );
+        assert_eq!(
+            "(Interp x (Pair 0 1) (Pair (Add 1 2) y))",
+            sexp(&parse_test("interp(x, (0, 1), (1+2, y))"))
+        );
+        assert_eq!("(SS a .a)", sexp(&parse_test("ss(a, .a)")));
     }
     #[test]
     fn test_plus() {
-        assert_eq!(node!(Add, leaf!(5), leaf!(7)), parse_test("5+7"));
-        assert_eq!(leaf!(12), parse(lex("5+7".into()), EmptyCtx).ok().unwrap());
+        assert_eq!(node!(Add, 0..3, leaf!(5, 0..1), leaf!(7, 2..3)), parse_test("5+7"));
+        assert_eq!(leaf!(12, 0..3), parse(lex("5+7").unwrap(), EmptyCtx).ok().unwrap());
+    }
+    #[test]
+    fn test_spans() {
+        let inp = "2 * min(-1, x)";
+        let res = node!(
+            Mul,
+            0..14,
+            leaf!(2, 0..1),
+            Ast::Node(
+                vec![
+                    node!(Minus, 8..10, leaf!(1, 9..10)),
+                    Ast::Get(Getter::parse("x").unwrap(), Span::new(12, 13)),
+                ],
+                Fun::Min,
+                Span::new(4, 14),
+            )
+        );
+        assert_eq!(res, parse_test(inp));
+    }
+    #[test]
+    fn test_arity() {
+        let e = parse_fn(lex("if(1,2)").unwrap(), applicators::empty, EmptyCtx);
+        let err = KilaError::Arity {
+            fun: Fun::If,
+            expected: Signature::Fixed(3),
+            found: 2,
+            span: Span::new(0, 7),
+        };
+        assert_eq!(Err(err), e);
+        let e = parse_fn(lex("interpoloi(1,2,3)").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(103), e.map_err(|e| e.code()));
+        let e = parse_fn(lex("min()").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(103), e.map_err(|e| e.code()));
+        assert!(parse_fn(lex("interpoloi(1,2,3,4,5)").unwrap(), applicators::empty, EmptyCtx).is_ok());
+        assert!(parse_fn(lex("max(min(1,2,3), abs(2), 4)").unwrap(), applicators::basic, EmptyCtx).is_ok());
+    }
+    #[test]
+    fn test_malformed() {
+        let code = |s: &str| parse_fn(lex(s).unwrap(), applicators::basic, EmptyCtx).map_err(|e| e.code());
+        assert_eq!(Err(101), code("min("));
+        assert_eq!(Err(102), code("5+"));
+        assert_eq!(Err(102), code("5 5"));
+        assert_eq!(Err(102), code("1, 2"));
+        assert_eq!(Err(102), code("()"));
+        assert_eq!(Err(102), code("min 5"));
+        assert_eq!(Err(102), code("*5"));
+        assert_eq!(Err(102), code("%5"));
+        assert_eq!(Err(102), code("2^"));
+        assert_eq!(Err(102), code("min(1 2)"));
+        assert_eq!(Err(101), code("(1+2"));
+        assert_eq!(Err(106), code("2*.a..b"));
+        assert_eq!(Err(101), code("(1,"));
+        assert_eq!(Err(103), code("(1, 2, 3)"));
+        assert_eq!(Err(103), code("interp(1)"));
+    }
+    #[test]
+    fn test_validate() {
+        let s = Span::default();
+        assert!(validate(&Ast::Node(vec![Ast::Leaf(Value::Num(1.0), s)], Fun::Abs, s)).is_ok());
+        let bad = Ast::Node(vec![Ast::Node(vec![], Fun::Abs, s)], Fun::Minus, s);
+        assert_eq!(Err(103), validate(&bad).map_err(|e| e.code()));
+    }
+    #[test]
+    fn test_unbalanced() {
+        let e = parse_fn(lex("min(5, 2").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(KilaError::UnbalancedParens(Span::new(3, 4))), e);
+        let e = parse_fn(lex("5+2)").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(KilaError::UnbalancedParens(Span::new(3, 4))), e);
+        // Brackets must match.
+        let e = parse_fn(lex("[1,2)").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(KilaError::UnbalancedParens(Span::new(0, 5))), e);
+        let e = parse_fn(lex("1+(1,2]").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(KilaError::UnbalancedParens(Span::new(2, 7))), e);
+        let e = parse_fn(lex("max(1]").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(KilaError::UnbalancedParens(Span::new(3, 6))), e);
+        let e = parse_fn(lex("[)").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(KilaError::UnbalancedParens(Span::new(0, 2))), e);
+        let e = parse_fn(lex("1]").unwrap(), applicators::empty, EmptyCtx);
+        assert_eq!(Err(KilaError::UnbalancedParens(Span::new(1, 2))), e);
+        assert!(parse_fn(lex("[1, (2), []]").unwrap(), applicators::empty, EmptyCtx).is_ok());
+    }
+    #[test]
+    fn test_display() {
+        let cases = [
+            ("a-(b-c)", "a - (b - c)"),
+            ("(a-b)-c", "a - b - c"),
+            ("2^3^4", "2 ^ 3 ^ 4"),
+            ("(2^3)^4", "(2 ^ 3) ^ 4"),
+            ("-a^2", "-a ^ 2"),
+            ("(-a)^2", "(-a) ^ 2"),
+            ("-(a+b)*c", "-(a + b) * c"),
+            ("a - -b", "a - -b"),
+            ("pienin(.a*muk, [1,2])", "min(.a * muk, [1, 2])"),
+            ("interp(a,(0,0),(10,5))", "interp(a, (0, 0), (10, 5))"),
+            ("if(a>=1,12:30,b)", "if(a >= 1, 12:30:00, b)"),
+            ("max(a,-)", "max(a, -)"),
+            ("-", "-"),
+        ];
+        for &(f, normal) in cases.iter() {
+            let ast = parse_test(f);
+            assert_eq!(normal, ast.to_string());
+            assert_eq!(sexp(&ast), sexp(&parse_test(normal)), "{}", f);
+        }
+        // Empty nodes and values are written so they parse back.
+        let s = Span::default();
+        let empty = Ast::Node(
+            vec![Ast::Empty(s), Ast::Leaf(Value::Empty, s), Ast::Node(vec![Ast::Empty(s)], Fun::Minus, s)],
+            Fun::Max,
+            s,
+        );
+        assert_eq!("max(-, -, --)", empty.to_string());
+        assert_eq!("(Max _ _ (Minus _))", sexp(&parse_test(&empty.to_string())));
     }
 }
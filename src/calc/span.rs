@@ -0,0 +1,80 @@
+//! Source positions. Every token and AST node carries a span pointing back
+//! into the formula string it was read from, so errors can be underlined.
+
+use std::fmt;
+
+/// Byte range `start..end` in the original formula string.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: usize::min(self.start, other.start),
+            end: usize::max(self.end, other.end),
+        }
+    }
+
+    /// The part of `s` this span points at.
+    pub fn slice<'a>(&self, s: &'a str) -> &'a str {
+        &s[self.start..self.end]
+    }
+
+    /// Zero-based character columns `(start, end)` of this span in `s`.
+    /// Differs from the byte offsets as soon as `s` contains non-ASCII
+    /// characters such as `ä`.
+    pub fn columns(&self, s: &str) -> (usize, usize) {
+        let start = s[..self.start].chars().count();
+        (start, start + self.slice(s).chars().count())
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Anything with a span attached. Lexer returns tokens wrapped in this.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Spanned<T> {
+        Spanned { node, span }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.node, self.span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to() {
+        assert_eq!(Span::new(0, 7), Span::new(3, 7).to(Span::new(0, 1)));
+    }
+    #[test]
+    fn test_columns() {
+        let s = "pienin(.ä, 5)";
+        let span = Span::new(10, 12);
+        assert_eq!(", ", span.slice(s));
+        assert_eq!((9, 11), span.columns(s));
+    }
+}
@@ -0,0 +1,194 @@
+//! Values formulas evaluate to and the rules of how they combine.
+//!
+//! Missing inputs are `Value::Empty`. Like in Kipa, an empty value makes
+//! every operator and function it is given to empty as well, so a team that
+//! did not do a task gets no result instead of a made up one. Aggregates
+//! like `min` skip empty values instead, see `eval`.
+
+use std::fmt;
+
+use super::error::KilaError;
+use super::parser::Fun;
+use super::span::Span;
+
+/// Seconds in a day. Clock times wrap around at midnight.
+pub const DAY: f64 = 86400.0;
+
+/// Value returned by the eval function.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Value {
+    Num(f64),
+    /// Result of a comparison. Counts as 1 or 0 in arithmetic.
+    Bool(bool),
+    /// Clock time in seconds since midnight.
+    Time(f64),
+    /// Length of time in seconds.
+    Duration(f64),
+    List(Vec<Value>),
+    /// No input given ("tyhjä").
+    Empty,
+}
+
+impl Value {
+    /// The number this value stands for in arithmetic. Times and durations
+    /// are seconds. Lists and empty values have none.
+    pub fn as_num(&self) -> Option<f64> {
+        match *self {
+            Value::Num(n) | Value::Time(n) | Value::Duration(n) => Some(n),
+            Value::Bool(b) => Some(if b { 1.0 } else { 0.0 }),
+            Value::List(_) | Value::Empty => None,
+        }
+    }
+
+    /// Name of the type for error messages.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Value::Num(_) => "number",
+            Value::Bool(_) => "boolean",
+            Value::Time(_) => "time",
+            Value::Duration(_) => "duration",
+            Value::List(_) => "list",
+            Value::Empty => "empty",
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Value::Empty
+    }
+
+    /// Whether value counts as true in `if`. Everything but zero does.
+    pub fn truthy(&self) -> bool {
+        self.as_num().map(|n| n != 0.0).unwrap_or(false)
+    }
+
+    /// Applies a binary operator. Lists are combined element by element and
+    /// a single value is combined with every element of a list, so
+    /// `.a*..mukana` and `.a-1` work like in Kipa.
+    pub fn binary(fun: Fun, a: Value, b: Value, span: Span) -> Result<Value, KilaError> {
+        match (a, b) {
+            (Value::List(x), Value::List(y)) => {
+                if x.len() != y.len() {
+                    return Err(KilaError::TypeMismatch {
+                        expected: "lists of equal length",
+                        found: "lists of different length",
+                        span,
+                    });
+                }
+                let v = x.into_iter()
+                    .zip(y)
+                    .map(|(x, y)| Value::binary(fun, x, y, span))
+                    .collect::<Result<Vec<Value>, KilaError>>()?;
+                Ok(Value::List(v))
+            }
+            (Value::List(x), b) => {
+                let v = x.into_iter()
+                    .map(|x| Value::binary(fun, x, b.clone(), span))
+                    .collect::<Result<Vec<Value>, KilaError>>()?;
+                Ok(Value::List(v))
+            }
+            (a, Value::List(y)) => {
+                let v = y.into_iter()
+                    .map(|y| Value::binary(fun, a.clone(), y, span))
+                    .collect::<Result<Vec<Value>, KilaError>>()?;
+                Ok(Value::List(v))
+            }
+            (a, b) => scalar(fun, a, b, span),
+        }
+    }
+}
+
+/// Binary operator on two values that are not lists.
+fn scalar(fun: Fun, a: Value, b: Value, span: Span) -> Result<Value, KilaError> {
+    let (x, y) = match (a.as_num(), b.as_num()) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Ok(Value::Empty),
+    };
+    Ok(match fun {
+        Fun::Eq => Value::Bool(x == y),
+        Fun::Neq => Value::Bool(x != y),
+        Fun::Lt => Value::Bool(x < y),
+        Fun::Le => Value::Bool(x <= y),
+        Fun::Gt => Value::Bool(x > y),
+        Fun::Ge => Value::Bool(x >= y),
+        Fun::Div | Fun::Mod if y == 0.0 => return Err(KilaError::DivisionByZero(span)),
+        Fun::Add | Fun::Sub => {
+            let n = if fun == Fun::Add { x + y } else { x - y };
+            match (a, b) {
+                (Value::Time(_), Value::Time(_)) if fun == Fun::Sub => Value::Duration(n),
+                (Value::Time(_), Value::Duration(_)) |
+                (Value::Duration(_), Value::Time(_)) => Value::Time(n.rem_euclid(DAY)),
+                (Value::Duration(_), Value::Duration(_)) => Value::Duration(n),
+                _ => Value::Num(n),
+            }
+        }
+        Fun::Mul => match (a, b) {
+            (Value::Duration(_), Value::Num(_)) | (Value::Num(_), Value::Duration(_)) => {
+                Value::Duration(x * y)
+            }
+            _ => Value::Num(x * y),
+        },
+        Fun::Div => match (a, b) {
+            (Value::Duration(_), Value::Num(_)) => Value::Duration(x / y),
+            _ => Value::Num(x / y),
+        },
+        Fun::Mod => Value::Num(x % y),
+        Fun::Pow => Value::Num(x.powf(y)),
+        _ => return Err(KilaError::UnknownFunction(fun, span)),
+    })
+}
+
+/// Formats seconds as `h:mm:ss`.
+fn clock(f: &mut fmt::Formatter, secs: f64) -> fmt::Result {
+    let s = secs.round() as i64;
+    let sign = if s < 0 { "-" } else { "" };
+    let s = s.abs();
+    write!(f, "{}{}:{:02}:{:02}", sign, s / 3600, s / 60 % 60, s % 60)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Num(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Time(t) | Value::Duration(t) => clock(f, t),
+            Value::List(ref v) => {
+                let s: Vec<String> = v.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", s.join(", "))
+            }
+            Value::Empty => write!(f, "-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bin(fun: Fun, a: Value, b: Value) -> Value {
+        Value::binary(fun, a, b, Span::default()).unwrap()
+    }
+
+    #[test]
+    fn test_empty_propagates() {
+        assert_eq!(Value::Empty, bin(Fun::Add, Value::Num(1.0), Value::Empty));
+        assert_eq!(Value::Empty, bin(Fun::Lt, Value::Empty, Value::Num(1.0)));
+    }
+    #[test]
+    fn test_time() {
+        let t = bin(Fun::Sub, Value::Time(3600.0), Value::Time(1800.0));
+        assert_eq!(Value::Duration(1800.0), t);
+        let t = bin(Fun::Add, Value::Time(DAY - 60.0), Value::Duration(120.0));
+        assert_eq!(Value::Time(60.0), t);
+        assert_eq!("0:01:00", t.to_string());
+        assert_eq!(Value::Num(30.0), bin(Fun::Div, Value::Duration(1800.0), Value::Duration(60.0)));
+    }
+    #[test]
+    fn test_lists() {
+        let a = Value::List(vec![Value::Num(1.0), Value::Empty, Value::Num(3.0)]);
+        let muk = Value::List(vec![Value::Num(1.0), Value::Num(1.0), Value::Empty]);
+        let res = Value::List(vec![Value::Num(1.0), Value::Empty, Value::Empty]);
+        assert_eq!(res, bin(Fun::Mul, a.clone(), muk));
+        let res = Value::List(vec![Value::Bool(false), Value::Empty, Value::Bool(true)]);
+        assert_eq!(res, bin(Fun::Gt, a, Value::Num(2.0)));
+    }
+}
@@ -0,0 +1,246 @@
+//! Imports inputs of a task from a CSV table, like one exported from a
+//! spreadsheet:
+//!
+//! ```text
+//! vartio;nimi;rasti.aika;rasti.virheet;arvio.a
+//! 1;Kotkat;12:30;2;7,5
+//! 2;Ketut;12:41;;8
+//! ```
+//!
+//! There is a row for every team and a column for every input. The column
+//! `vartio` has the number of the team and is required. Other columns are
+//! named `subtask.input`, or just `input` if no other subtask of the task
+//! has an input of that name. A column `nimi` is ignored. An empty cell
+//! leaves the input empty. Every row must have as many cells as the header.
+
+use std::collections::HashSet;
+use std::io::Read;
+
+use csv::{ReaderBuilder, Trim};
+
+use super::error::{CellError, LoadError};
+use super::{Competition, InputId, InputKind};
+
+/// How the table is written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Numbers are written `7,5` instead of `7.5`.
+    pub decimal_comma: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        CsvOptions {
+            delimiter: b',',
+            decimal_comma: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// The way spreadsheets in Finnish write CSV: `;` between cells and
+    /// decimal comma.
+    pub fn finnish() -> CsvOptions {
+        CsvOptions {
+            delimiter: b';',
+            decimal_comma: true,
+        }
+    }
+}
+
+/// What a column of the table is.
+enum Column {
+    Team,
+    Ignored,
+    Input(InputId, InputKind),
+}
+
+/// Imports inputs of task `task` of series `series` from `data`. Nothing is
+/// imported if any cell is wrong, all wrong cells are returned instead.
+/// Returns the number of teams imported.
+pub fn import<R: Read>(
+    c: &mut Competition,
+    series: &str,
+    task: &str,
+    data: R,
+    opts: CsvOptions,
+) -> Result<usize, LoadError> {
+    let si = c.find_series(series)
+        .ok_or_else(|| LoadError::Invalid(format!("no series {}", series)))?;
+    let s = &c.series[si];
+    let ti = s.find_task(task)
+        .ok_or_else(|| LoadError::Invalid(format!("no task {} in series {}", task, series)))?;
+    let mut reader = ReaderBuilder::new()
+        .delimiter(opts.delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(data);
+    let mut errors = Vec::new();
+    let mut records = reader.records();
+    let header = match records.next() {
+        Some(h) => h?,
+        None => return Err(LoadError::Invalid("empty table".into())),
+    };
+    let mut columns = Vec::new();
+    for (i, h) in header.iter().enumerate() {
+        let col = match h {
+            "vartio" => Ok(Column::Team),
+            "nimi" => Ok(Column::Ignored),
+            h => input_column(c, si, ti, h),
+        };
+        match col {
+            Ok(col) => columns.push(col),
+            Err(message) => {
+                errors.push(CellError {
+                    row: 1,
+                    column: i + 1,
+                    message,
+                });
+                columns.push(Column::Ignored);
+            }
+        }
+    }
+    let team_col = match columns.iter().position(|c| matches!(*c, Column::Team)) {
+        Some(i) => i,
+        None => return Err(LoadError::Invalid("no column vartio".into())),
+    };
+    let mut values = Vec::new();
+    let mut seen = HashSet::new();
+    for (r, rec) in records.enumerate() {
+        let rec = rec?;
+        let row = r + 2;
+        let cell = |column: usize, message: String| CellError {
+            row,
+            column: column + 1,
+            message,
+        };
+        // Points at the first missing or extra cell.
+        if rec.len() != columns.len() {
+            let message = format!("row has {} cells but header has {}", rec.len(), columns.len());
+            errors.push(cell(usize::min(rec.len(), columns.len()), message));
+            continue;
+        }
+        let number = &rec[team_col];
+        let team = match number.parse().ok().and_then(|n| s.find_team(n).map(|t| (n, t))) {
+            Some((n, t)) if seen.insert(n) => t,
+            Some(_) => {
+                errors.push(cell(team_col, format!("team {} twice", number)));
+                continue;
+            }
+            None => {
+                errors.push(cell(team_col, format!("no team {} in series {}", number, series)));
+                continue;
+            }
+        };
+        for (i, col) in columns.iter().enumerate() {
+            if let Column::Input(id, kind) = *col {
+                let text = if opts.decimal_comma && kind == InputKind::Num {
+                    rec[i].replace(',', ".")
+                } else {
+                    rec[i].to_string()
+                };
+                match kind.parse(&text) {
+                    Ok(v) => values.push((team, id, v)),
+                    Err(e) => errors.push(cell(i, e)),
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(LoadError::Cells(errors));
+    }
+    let teams = seen.len();
+    let s = &mut c.series[si];
+    for (team, id, v) in values {
+        s.teams[team].set(id, v);
+    }
+    Ok(teams)
+}
+
+/// Finds the input of column `h`, `subtask.input` or `input`.
+fn input_column(c: &Competition, series: usize, task: usize, h: &str) -> Result<Column, String> {
+    let s = &c.series[series];
+    let t = &s.tasks[task];
+    let id = match h.find('.') {
+        Some(d) => t.find_subtask(&h[..d]).and_then(|o| s.input_in(task, o, &h[d + 1..])),
+        None => {
+            let ids: Vec<InputId> = (0..t.subtasks.len())
+                .filter_map(|o| s.input_in(task, o, h))
+                .collect();
+            if ids.len() > 1 {
+                return Err(format!("input {} is in many subtasks, use subtask.{}", h, h));
+            }
+            ids.first().cloned()
+        }
+    };
+    match id {
+        Some(id) => Ok(Column::Input(id, t.subtasks[id.subtask].inputs[id.input].kind)),
+        None => Err(format!("no input {} in task {}", h, t.name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calc::Value;
+    use competition::{InputDef, Series, Subtask, Task, Team};
+
+    fn competition() -> Competition {
+        let mut s = Series::new("sarja");
+        let mut t = Task::new("rasti");
+        let mut o = Subtask::new("rasti", "a", &["virheet"]);
+        o.inputs.push(InputDef {
+            name: "aika".into(),
+            kind: InputKind::Time,
+        });
+        t.subtasks.push(o);
+        t.subtasks.push(Subtask::new("arvio", "a", &["a", "virheet"]));
+        s.tasks.push(t);
+        s.teams.push(Team::new(1, "Kotkat"));
+        s.teams.push(Team::new(2, "Ketut"));
+        let mut c = Competition::new("kisa");
+        c.series.push(s);
+        c
+    }
+
+    fn cells(r: Result<usize, LoadError>) -> Vec<(usize, usize)> {
+        match r {
+            Err(LoadError::Cells(c)) => c.iter().map(|c| (c.row, c.column)).collect(),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_import() {
+        let mut c = competition();
+        let data = "vartio;nimi;rasti.aika;rasti.virheet;a\n1;Kotkat;12:30;2;7,5\n2;Ketut;12:41;;8\n";
+        assert_eq!(2, import(&mut c, "sarja", "rasti", data.as_bytes(), CsvOptions::finnish()).unwrap());
+        let s = &c.series[0];
+        let v = |team: usize, key: &str| s.teams[team].value(s.find_key(key).unwrap());
+        assert_eq!(Value::Time(45000.0), v(0, "rasti.rasti.aika"));
+        assert_eq!(Value::Num(2.0), v(0, "rasti.rasti.virheet"));
+        assert_eq!(Value::Num(7.5), v(0, "rasti.arvio.a"));
+        assert_eq!(Value::Empty, v(1, "rasti.rasti.virheet"));
+        let data = "a,vartio\n\"7.5\",2\n";
+        assert_eq!(1, import(&mut c, "sarja", "rasti", data.as_bytes(), CsvOptions::default()).unwrap());
+    }
+    #[test]
+    fn test_errors() {
+        let mut c = competition();
+        let data = "vartio;virheet;b\n1;x;\n3;1;\n1;2;\n2;1,5;\n";
+        let r = import(&mut c, "sarja", "rasti", data.as_bytes(), CsvOptions::finnish());
+        assert_eq!(vec![(1, 2), (1, 3), (3, 1), (4, 1)], cells(r));
+        let data = "vartio;a\n1;x\n";
+        let r = import(&mut c, "sarja", "rasti", data.as_bytes(), CsvOptions::finnish());
+        assert_eq!(vec![(2, 2)], cells(r));
+        // Short and long rows.
+        let data = "vartio;a;rasti.virheet\n1;2\n2;1;0;5\n";
+        let r = import(&mut c, "sarja", "rasti", data.as_bytes(), CsvOptions::finnish());
+        assert_eq!(vec![(2, 3), (3, 4)], cells(r));
+        assert_eq!(c, competition());
+        let r = import(&mut c, "sarja", "rasti", "a\n1".as_bytes(), CsvOptions::default());
+        assert_eq!("invalid competition: no column vartio", r.unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,249 @@
+//! `KilaCtx` over the competition model. Resolves the getters of Kipa
+//! formulas, see `calc::getter`. Names in getters are the names of the
+//! tasks, subtasks and inputs of the series.
+
+use calc::check::Shape;
+use calc::ctx::KilaCtx;
+use calc::error::KilaError;
+use calc::getter::{Getter, Path, Scope};
+use calc::span::Span;
+use calc::Value;
+
+use super::{Competition, InputId, Series};
+
+/// Evaluates formulas of a subtask for a team. Copying it is cheap, it only
+/// borrows the competition.
+#[derive(Debug, Clone, Copy)]
+pub struct CompetitionCtx<'a> {
+    pub competition: &'a Competition,
+    pub series: usize,
+    pub task: usize,
+    pub subtask: usize,
+    /// Index of the team in the series.
+    pub team: usize,
+}
+
+impl<'a> CompetitionCtx<'a> {
+    pub fn new(
+        competition: &'a Competition,
+        series: usize,
+        task: usize,
+        subtask: usize,
+        team: usize,
+    ) -> CompetitionCtx<'a> {
+        CompetitionCtx {
+            competition,
+            series,
+            task,
+            subtask,
+            team,
+        }
+    }
+
+    fn series(&self) -> &'a Series {
+        &self.competition.series[self.series]
+    }
+
+    /// `muk`: 1 for the teams taking part, empty for others.
+    fn participation(&self) -> Value {
+        Value::List(
+            self.series()
+                .teams
+                .iter()
+                .map(|t| if t.participating { Value::Num(1.0) } else { Value::Empty })
+                .collect(),
+        )
+    }
+
+    /// Series, task and input `p` points at, and the team of its scope, or
+    /// none for the whole series.
+    fn locate(&self, g: &Getter, p: &Path) -> Result<(&'a Series, usize, InputId, Option<usize>), KilaError> {
+        let unknown = || KilaError::UnknownIdentifier(g.to_string(), Span::default());
+        let mut series = self.series();
+        if let Some(ref s) = p.series {
+            let s = self.competition.find_series(s).ok_or_else(unknown)?;
+            series = &self.competition.series[s];
+        }
+        let task = match p.task {
+            Some(ref t) => series.find_task(t).ok_or_else(unknown)?,
+            None => self.task,
+        };
+        let subtask = match p.subtask {
+            Some(ref s) => series.tasks[task].find_subtask(s).ok_or_else(unknown)?,
+            None => self.subtask,
+        };
+        let id = series.input_in(task, subtask, &p.input).ok_or_else(unknown)?;
+        let team = match p.scope {
+            Scope::Own => Some(self.team),
+            Scope::Team(n) => match series.find_team(n) {
+                Some(t) => Some(t),
+                None => {
+                    return Err(KilaError::Context(
+                        format!("no team {} in series {}", n, series.name),
+                        Span::default(),
+                    ))
+                }
+            },
+            Scope::Series => None,
+        };
+        Ok((series, task, id, team))
+    }
+
+    /// Shape of the value of getter `g`, without getting it. Fails like
+    /// `get` if the getter does not name an input.
+    pub fn shape(&self, g: &Getter) -> Result<Shape, KilaError> {
+        match *g {
+            Getter::Number | Getter::Disqualified | Getter::Penalty => Ok(Shape::Single),
+            Getter::Participation => Ok(Shape::List),
+            Getter::Input(ref p) => match self.locate(g, p)? {
+                (_, _, _, Some(_)) => Ok(Shape::Single),
+                (_, _, _, None) => Ok(Shape::List),
+            },
+        }
+    }
+
+    /// Value of an input.
+    fn input(&self, g: &Getter, p: &Path) -> Result<Value, KilaError> {
+        let (series, task, id, team) = self.locate(g, p)?;
+        // Inputs of teams disqualified from the task count only for
+        // themselves.
+        let counts = |t: usize| {
+            let team = &series.teams[t];
+            let own = t == self.team && p.series.is_none();
+            (!p.participating || team.participating) && (own || !team.disqualified(task))
+        };
+        Ok(match team {
+            Some(t) if counts(t) => series.teams[t].value(id),
+            Some(_) => Value::Empty,
+            None => Value::List(
+                (0..series.teams.len())
+                    .map(|t| if counts(t) { series.teams[t].value(id) } else { Value::Empty })
+                    .collect(),
+            ),
+        })
+    }
+}
+
+impl<'a> KilaCtx for CompetitionCtx<'a> {
+    fn get(&self, g: &Getter) -> Result<Value, KilaError> {
+        match *g {
+            Getter::Number => Ok(Value::Num(self.series().teams[self.team].number as f64)),
+            Getter::Participation => Ok(self.participation()),
+            Getter::Disqualified => Ok(Value::Bool(self.series().teams[self.team].disqualified(self.task))),
+            Getter::Penalty => Ok(Value::Num(self.series().teams[self.team].penalty(self.task))),
+            Getter::Input(ref p) => self.input(g, p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calc::calculate_err;
+    use competition::{Series, Subtask, Task, TaskStatus, Team};
+
+    /// Series of five teams, team 3 did not return input a and team 5 does
+    /// not take part in the competition.
+    fn competition() -> Competition {
+        let mut s = Series::new("sarja");
+        let mut t = Task::new("start");
+        t.subtasks.push(Subtask::new("a", "a", &["a", "b"]));
+        t.subtasks.push(Subtask::new("c", "a", &["a"]));
+        s.tasks.push(t);
+        let a = s.find_input("start", "a", "a").unwrap();
+        let c = s.find_input("start", "c", "a").unwrap();
+        for (n, v) in [12.0, 8.0, 0.0, 20.0, 100.0].iter().enumerate() {
+            let mut team = Team::new(n as u32 + 1, "");
+            if n != 2 {
+                team.set(a, Value::Num(*v));
+            }
+            team.set(c, Value::Num(*v + 1.0));
+            team.participating = n != 4;
+            s.teams.push(team);
+        }
+        let mut c = Competition::new("kisa");
+        c.series.push(s);
+        c.series.push(Series::new("toinen"));
+        c
+    }
+
+    #[test]
+    fn test_getters() {
+        let c = competition();
+        let ctx = CompetitionCtx::new(&c, 0, 0, 0, 0);
+        let get = |g: &str| ctx.get(&Getter::parse(g).unwrap());
+        let n = |v: f64| Value::Num(v);
+        let e = Value::Empty;
+        assert_eq!(Ok(n(12.0)), get("a"));
+        assert_eq!(Ok(e.clone()), get("b"));
+        assert_eq!(Ok(n(1.0)), get("vartio"));
+        let all = Value::List(vec![n(12.0), n(8.0), e.clone(), n(20.0), n(100.0)]);
+        assert_eq!(Ok(all.clone()), get(".a"));
+        assert_eq!(Ok(all), get("sarja.start.a.a"));
+        assert_eq!(Ok(n(12.0)), get(".a.vartio"));
+        assert_eq!(Ok(n(20.0)), get(".a.4"));
+        assert_eq!(Ok(n(9.0)), get(".c.a.2"));
+        assert_eq!(Ok(n(13.0)), get("..start.c.a.vartio"));
+        let muk = Value::List(vec![n(1.0), n(1.0), n(1.0), n(1.0), e.clone()]);
+        assert_eq!(Ok(muk.clone()), get("muk"));
+        assert_eq!(Ok(muk), get("..mukana"));
+        let filtered = Value::List(vec![n(12.0), n(8.0), e.clone(), n(20.0), e]);
+        assert_eq!(Ok(filtered), get("muk.a"));
+    }
+    #[test]
+    fn test_unknown() {
+        let c = competition();
+        let ctx = CompetitionCtx::new(&c, 0, 0, 0, 0);
+        let get = |g: &str| ctx.get(&Getter::parse(g).unwrap());
+        assert_eq!(Err(104), get("x").map_err(|e| e.code()));
+        assert_eq!(Err(104), get("..loppu.a.a").map_err(|e| e.code()));
+        assert_eq!(Err(300), get(".a.9").map_err(|e| e.code()));
+    }
+    #[test]
+    fn test_formulas() {
+        let c = competition();
+        let ctx = CompetitionCtx::new(&c, 0, 0, 0, 0);
+        let calc = |f: &str| calculate_err(f.into(), ctx);
+        assert_eq!(Ok(Some(20.0)), calc("max(.a*muk)"));
+        assert_eq!(Ok(Some(12.0)), calc("med(.a*..mukana)"));
+        assert_eq!(Ok(Some(6.0)), calc("a/max(.a*muk)*10"));
+        let ctx = CompetitionCtx::new(&c, 0, 0, 0, 2);
+        assert_eq!(Ok(None), calculate_err("a*2".into(), ctx));
+    }
+    #[test]
+    fn test_statuses() {
+        let mut c = competition();
+        c.series[0].teams[1].statuses.insert(0, TaskStatus::Disqualified);
+        c.series[0].teams[3].statuses.insert(0, TaskStatus::Penalty(2.5));
+        let get = |c: &Competition, team: usize, g: &str| {
+            CompetitionCtx::new(c, 0, 0, 0, team).get(&Getter::parse(g).unwrap())
+        };
+        let n = |v: f64| Value::Num(v);
+        let e = Value::Empty;
+        assert_eq!(Ok(Value::Bool(true)), get(&c, 1, "vartio.hylatty"));
+        assert_eq!(Ok(Value::Bool(false)), get(&c, 0, "vartio.hylatty"));
+        assert_eq!(Ok(n(2.5)), get(&c, 3, "vartio.sakko"));
+        assert_eq!(Ok(n(0.0)), get(&c, 1, "vartio.sakko"));
+        let all = Value::List(vec![n(12.0), e.clone(), e.clone(), n(20.0), n(100.0)]);
+        assert_eq!(Ok(all), get(&c, 0, ".a"));
+        assert_eq!(Ok(e), get(&c, 0, ".a.2"));
+        assert_eq!(Ok(n(8.0)), get(&c, 1, ".a.2"));
+        let points: Vec<Option<f64>> = c.calculate()
+            .into_iter()
+            .filter(|p| p.subtask == 0)
+            .map(|p| p.points.unwrap())
+            .collect();
+        assert_eq!(vec![Some(12.0), Some(0.0), None, Some(20.0), Some(100.0)], points);
+    }
+    #[test]
+    fn test_shape() {
+        let c = competition();
+        let shape = |g: &str| CompetitionCtx::new(&c, 0, 0, 0, 0).shape(&Getter::parse(g).unwrap()).map_err(|e| e.code());
+        assert_eq!(Ok(Shape::Single), shape("b"));
+        assert_eq!(Ok(Shape::Single), shape(".c.a.4"));
+        assert_eq!(Ok(Shape::List), shape("muk.a"));
+        assert_eq!(Ok(Shape::List), shape("sarja.start.c.a"));
+        assert_eq!(Err(104), shape(".d.a"));
+        assert_eq!(Err(300), shape(".a.9"));
+    }
+}
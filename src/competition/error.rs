@@ -0,0 +1,80 @@
+//! Errors of loading a competition.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use csv;
+use rusqlite;
+use serde_json;
+
+/// Error returned when a competition can not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    Sqlite(rusqlite::Error),
+    /// Competition was read but does not make sense, e.g. a team has an
+    /// input that is not defined.
+    Invalid(String),
+    /// Problems found in the cells of an imported table.
+    Cells(Vec<CellError>),
+}
+
+/// Problem in a cell of an imported table. Rows and columns count from 1
+/// and the header is row 1, like in a spreadsheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellError {
+    pub row: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "row {}, column {}: {}", self.row, self.column, self.message)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "io: {}", e),
+            LoadError::Json(ref e) => write!(f, "json: {}", e),
+            LoadError::Csv(ref e) => write!(f, "csv: {}", e),
+            LoadError::Sqlite(ref e) => write!(f, "sqlite: {}", e),
+            LoadError::Invalid(ref m) => write!(f, "invalid competition: {}", m),
+            LoadError::Cells(ref c) => {
+                let c: Vec<String> = c.iter().map(|c| c.to_string()).collect();
+                write!(f, "{}", c.join("; "))
+            }
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+impl From<csv::Error> for LoadError {
+    fn from(e: csv::Error) -> LoadError {
+        LoadError::Csv(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> LoadError {
+        LoadError::Json(e)
+    }
+}
+
+impl From<rusqlite::Error> for LoadError {
+    fn from(e: rusqlite::Error) -> LoadError {
+        LoadError::Sqlite(e)
+    }
+}
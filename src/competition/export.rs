@@ -0,0 +1,380 @@
+//! Exports results of a competition for publishing: standings of a series
+//! and breakdowns of a task as CSV, and all results as JSON or as a
+//! self-contained HTML page.
+//!
+//! Exporters take the points of the subtasks, from `Competition::calculate`
+//! or `sqlite::Db::calculate`, and rank the teams with `results::score`.
+//! Points are rounded to two decimals. Subtasks whose formula failed are
+//! written as `virhe <code>`, see doc/protocol.md for the codes.
+
+use std::collections::{BTreeMap, HashMap};
+
+use csv::WriterBuilder;
+use serde_json;
+use serde_json::Value as Json;
+
+use calc::error::KilaError;
+use calc::Value;
+
+use super::csv::CsvOptions;
+use super::results::{score, SeriesResults, Standing};
+use super::{Competition, InputId, Points, Series, TieBreak};
+
+/// Points of a subtask, see `Points`.
+type SubtaskPoints = Result<Option<f64>, KilaError>;
+
+/// Results of a competition with the points of every subtask at hand.
+struct Report<'a> {
+    results: Vec<SeriesResults>,
+    /// Points by series, task, subtask and team.
+    points: HashMap<(usize, usize, usize, usize), &'a SubtaskPoints>,
+}
+
+impl<'a> Report<'a> {
+    fn new(c: &'a Competition, points: &'a [Points]) -> Report<'a> {
+        Report {
+            results: score(c, points),
+            points: points
+                .iter()
+                .map(|p| ((p.series, p.task, p.subtask, p.team), &p.points))
+                .collect(),
+        }
+    }
+
+    fn subtask(&self, series: usize, task: usize, subtask: usize, team: usize) -> Option<&SubtaskPoints> {
+        self.points.get(&(series, task, subtask, team)).cloned()
+    }
+}
+
+/// Points rounded to two decimals, without trailing zeros.
+fn number(v: f64, decimal_comma: bool) -> String {
+    let s = format!("{:.2}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    let s = if s == "-0" { "0" } else { s };
+    if decimal_comma {
+        s.replace('.', ",")
+    } else {
+        s.into()
+    }
+}
+
+fn points_text(p: Option<&SubtaskPoints>, decimal_comma: bool) -> String {
+    match p {
+        Some(Ok(Some(v))) => number(*v, decimal_comma),
+        Some(Err(e)) => format!("virhe {}", e.code()),
+        _ => String::new(),
+    }
+}
+
+fn value_text(v: &Value, decimal_comma: bool) -> String {
+    match *v {
+        Value::Num(n) => number(n, decimal_comma),
+        Value::Empty => String::new(),
+        ref v => v.to_string(),
+    }
+}
+
+fn rank_text(st: &Standing) -> String {
+    st.rank.map(|r| r.to_string()).unwrap_or_default()
+}
+
+/// Inputs of task `task` of the series with their column names
+/// `subtask.input`.
+fn task_inputs(s: &Series, task: usize) -> Vec<(String, InputId)> {
+    s.input_ids()
+        .into_iter()
+        .filter(|id| id.task == task)
+        .map(|id| {
+            let o = &s.tasks[task].subtasks[id.subtask];
+            (format!("{}.{}", o.name, o.inputs[id.input].name), id)
+        })
+        .collect()
+}
+
+fn write_csv(rows: Vec<Vec<String>>, opts: CsvOptions) -> String {
+    let mut w = WriterBuilder::new().delimiter(opts.delimiter).from_writer(Vec::new());
+    for row in rows {
+        if w.write_record(&row).is_err() {
+            return String::new();
+        }
+    }
+    w.into_inner().ok().and_then(|b| String::from_utf8(b).ok()).unwrap_or_default()
+}
+
+/// Standings of series `series` as CSV: place, number and name of the team,
+/// points of every task and the total. Teams not taking part have no
+/// place.
+pub fn standings_csv(c: &Competition, points: &[Points], series: usize, opts: CsvOptions) -> String {
+    let r = Report::new(c, points);
+    let s = &c.series[series];
+    let mut header: Vec<String> = vec!["sija".into(), "vartio".into(), "nimi".into()];
+    header.extend(s.tasks.iter().map(|t| t.name.clone()));
+    header.push("yhteensa".into());
+    let mut rows = vec![header];
+    for st in &r.results[series].standings {
+        let team = &s.teams[st.team];
+        let mut row = vec![rank_text(st), team.number.to_string(), team.name.clone()];
+        row.extend(st.tasks.iter().map(|p| p.map(|p| number(p, opts.decimal_comma)).unwrap_or_default()));
+        row.push(number(st.total, opts.decimal_comma));
+        rows.push(row);
+    }
+    write_csv(rows, opts)
+}
+
+/// Breakdown of task `task` of series `series` as CSV: place, number and
+/// name of the team, its inputs as columns `subtask.input`, points of every
+/// subtask and points of the task. Teams are in the order of the standings,
+/// as in the breakdowns of `to_html`.
+pub fn task_csv(c: &Competition, points: &[Points], series: usize, task: usize, opts: CsvOptions) -> String {
+    let r = Report::new(c, points);
+    let s = &c.series[series];
+    let t = &s.tasks[task];
+    let inputs = task_inputs(s, task);
+    let mut header: Vec<String> = vec!["sija".into(), "vartio".into(), "nimi".into()];
+    header.extend(inputs.iter().map(|i| i.0.clone()));
+    header.extend(t.subtasks.iter().map(|o| o.name.clone()));
+    header.push("pisteet".into());
+    let mut rows = vec![header];
+    for st in &r.results[series].standings {
+        let team = &s.teams[st.team];
+        let mut row = vec![rank_text(st), team.number.to_string(), team.name.clone()];
+        row.extend(inputs.iter().map(|i| value_text(&team.value(i.1), opts.decimal_comma)));
+        row.extend((0..t.subtasks.len()).map(|o| points_text(r.subtask(series, task, o, st.team), opts.decimal_comma)));
+        row.push(st.tasks[task].map(|p| number(p, opts.decimal_comma)).unwrap_or_default());
+        rows.push(row);
+    }
+    write_csv(rows, opts)
+}
+
+#[derive(Serialize)]
+struct ResultsFile {
+    name: String,
+    series: Vec<SeriesFile>,
+}
+
+#[derive(Serialize)]
+struct SeriesFile {
+    name: String,
+    standings: Vec<StandingFile>,
+}
+
+#[derive(Serialize)]
+struct StandingFile {
+    rank: Option<usize>,
+    number: u32,
+    name: String,
+    total: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decided_by: Option<TieBreak>,
+    tasks: Vec<TaskFile>,
+}
+
+#[derive(Serialize)]
+struct TaskFile {
+    name: String,
+    points: Option<f64>,
+    subtasks: Vec<SubtaskFile>,
+}
+
+#[derive(Serialize)]
+struct SubtaskFile {
+    name: String,
+    points: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    inputs: BTreeMap<String, Json>,
+}
+
+/// All results as pretty printed JSON, see doc/json.md for the format.
+pub fn to_json(c: &Competition, points: &[Points]) -> String {
+    let r = Report::new(c, points);
+    let file = ResultsFile {
+        name: c.name.clone(),
+        series: r.results
+            .iter()
+            .map(|res| SeriesFile {
+                name: c.series[res.series].name.clone(),
+                standings: res.standings
+                    .iter()
+                    .map(|st| standing_file(&r, &c.series[res.series], res.series, st))
+                    .collect(),
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&file).unwrap_or_default()
+}
+
+fn standing_file(r: &Report, s: &Series, series: usize, st: &Standing) -> StandingFile {
+    let team = &s.teams[st.team];
+    let tasks = s.tasks
+        .iter()
+        .enumerate()
+        .map(|(ti, t)| TaskFile {
+            name: t.name.clone(),
+            points: st.tasks[ti],
+            subtasks: t.subtasks
+                .iter()
+                .enumerate()
+                .map(|(oi, o)| {
+                    let p = r.subtask(series, ti, oi, st.team);
+                    let inputs = (0..o.inputs.len()).map(|i| {
+                        let id = InputId {
+                            task: ti,
+                            subtask: oi,
+                            input: i,
+                        };
+                        let v = match team.value(id) {
+                            Value::Empty => Json::Null,
+                            Value::Num(n) => Json::from(n),
+                            v => Json::String(v.to_string()),
+                        };
+                        (o.inputs[i].name.clone(), v)
+                    });
+                    SubtaskFile {
+                        name: o.name.clone(),
+                        points: p.and_then(|p| p.clone().ok()).and_then(|p| p),
+                        error: p.and_then(|p| p.as_ref().err()).map(|e| e.to_string()),
+                        inputs: inputs.collect(),
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+    StandingFile {
+        rank: st.rank,
+        number: team.number,
+        name: team.name.clone(),
+        total: st.total,
+        decided_by: st.decided_by.clone(),
+        tasks,
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn row(out: &mut String, class: &str, cell: &str, cells: &[String]) {
+    if class.is_empty() {
+        out.push_str("<tr>");
+    } else {
+        out.push_str(&format!("<tr class=\"{}\">", class));
+    }
+    for c in cells {
+        out.push_str(&format!("<{0}>{1}</{0}>", cell, escape(c)));
+    }
+    out.push_str("</tr>\n");
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse;margin-bottom:2em}\
+th,td{border:1px solid #aaa;padding:.2em .6em;text-align:right}\
+th{background:#eee}td:nth-child(3){text-align:left}\
+tr.ulkopuolella{color:#888}";
+
+/// All results as a single HTML page that needs no other files: standings
+/// of every series followed by the breakdowns of its tasks. Rows of teams
+/// not taking part have class `ulkopuolella`.
+pub fn to_html(c: &Competition, points: &[Points]) -> String {
+    let r = Report::new(c, points);
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"fi\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n", escape(&c.name), STYLE));
+    out.push_str(&format!("<h1>{}</h1>\n", escape(&c.name)));
+    for res in &r.results {
+        let s = &c.series[res.series];
+        let class = |st: &Standing| if s.teams[st.team].participating { "" } else { "ulkopuolella" };
+        out.push_str(&format!("<h2>{}</h2>\n<table>\n", escape(&s.name)));
+        let mut header: Vec<String> = vec!["Sija".into(), "Vartio".into(), "Nimi".into()];
+        header.extend(s.tasks.iter().map(|t| t.name.clone()));
+        header.push("Yhteensä".into());
+        row(&mut out, "", "th", &header);
+        for st in &res.standings {
+            let team = &s.teams[st.team];
+            let mut cells = vec![rank_text(st), team.number.to_string(), team.name.clone()];
+            cells.extend(st.tasks.iter().map(|p| p.map(|p| number(p, false)).unwrap_or_default()));
+            cells.push(number(st.total, false));
+            row(&mut out, class(st), "td", &cells);
+        }
+        out.push_str("</table>\n");
+        for (ti, t) in s.tasks.iter().enumerate() {
+            let inputs = task_inputs(s, ti);
+            out.push_str(&format!("<h3>{}: {}</h3>\n<table>\n", escape(&s.name), escape(&t.name)));
+            let mut header: Vec<String> = vec!["Sija".into(), "Vartio".into(), "Nimi".into()];
+            header.extend(inputs.iter().map(|i| i.0.clone()));
+            header.extend(t.subtasks.iter().map(|o| o.name.clone()));
+            header.push("Pisteet".into());
+            row(&mut out, "", "th", &header);
+            for st in &res.standings {
+                let team = &s.teams[st.team];
+                let mut cells = vec![rank_text(st), team.number.to_string(), team.name.clone()];
+                cells.extend(inputs.iter().map(|i| value_text(&team.value(i.1), false)));
+                cells.extend((0..t.subtasks.len()).map(|o| points_text(r.subtask(res.series, ti, o, st.team), false)));
+                cells.push(st.tasks[ti].map(|p| number(p, false)).unwrap_or_default());
+                row(&mut out, class(st), "td", &cells);
+            }
+            out.push_str("</table>\n");
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use competition::json;
+
+    const EXAMPLE: &str = include_str!("../../doc/competition.json");
+
+    #[test]
+    fn test_number() {
+        assert_eq!("6.67", number(20.0 / 3.0, false));
+        assert_eq!("6,5", number(6.5, true));
+        assert_eq!("10", number(10.0, false));
+        assert_eq!("0", number(-0.001, false));
+    }
+    #[test]
+    fn test_csv() {
+        let c = json::from_str(EXAMPLE).unwrap();
+        let points = c.calculate();
+        assert_eq!(
+            "sija;vartio;nimi;suunnistus;ensiapu;yhteensa\n\
+             1;1;Kotkat;10;10;20\n\
+             2;2;Ketut;5;3,5;8,5\n\
+             ;3;Karhut;;7,5;7,5\n",
+            standings_csv(&c, &points, 0, CsvOptions::finnish())
+        );
+        assert_eq!(
+            "sija,vartio,nimi,aika.lahto,aika.maali,aika,pisteet\n\
+             1,1,Kotkat,10:00:00,10:45:30,10,10\n\
+             2,2,Ketut,10:05:00,11:13:15,5,5\n\
+             ,3,Karhut,,,,\n",
+            task_csv(&c, &points, 0, 0, CsvOptions::default())
+        );
+    }
+    #[test]
+    fn test_json() {
+        let c = json::from_str(EXAMPLE).unwrap();
+        let v: Json = serde_json::from_str(&to_json(&c, &c.calculate())).unwrap();
+        let first = &v["series"][0]["standings"][0];
+        assert_eq!(Json::from("Kotkat"), first["name"]);
+        assert_eq!(Json::from(1), first["rank"]);
+        assert_eq!(Json::from(20.0), first["total"]);
+        assert_eq!(Json::from("10:45:30"), first["tasks"][0]["subtasks"][0]["inputs"]["maali"]);
+        assert_eq!(Json::Null, v["series"][0]["standings"][2]["rank"]);
+    }
+    #[test]
+    fn test_html() {
+        let mut c = json::from_str(EXAMPLE).unwrap();
+        c.series[0].teams[0].name = "<Kotkat & co>".into();
+        let html = to_html(&c, &c.calculate());
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<td>&lt;Kotkat &amp; co&gt;</td>"));
+        assert!(html.contains("<tr class=\"ulkopuolella\"><td></td><td>3</td>"));
+        assert_eq!(3, html.matches("<table>").count());
+    }
+}
@@ -0,0 +1,257 @@
+//! Competition as a JSON file, see doc/json.md for the format.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde_json;
+use serde_json::Value as Json;
+
+use calc::Value;
+
+use super::error::LoadError;
+use super::{Competition, InputDef, InputKind, Series, Subtask, Task, TaskStatus, Team, TieBreak};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CompetitionFile {
+    name: String,
+    #[serde(default)]
+    series: Vec<SeriesFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SeriesFile {
+    name: String,
+    #[serde(default)]
+    tasks: Vec<TaskFile>,
+    #[serde(default)]
+    teams: Vec<TeamFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tie_breaks: Vec<TieBreak>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TaskFile {
+    name: String,
+    #[serde(default)]
+    subtasks: Vec<SubtaskFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SubtaskFile {
+    name: String,
+    formula: String,
+    #[serde(default)]
+    inputs: Vec<InputFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InputFile {
+    name: String,
+    #[serde(default = "num")]
+    kind: InputKind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamFile {
+    number: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "yes")]
+    participating: bool,
+    /// Values by `task.subtask.input`.
+    #[serde(default)]
+    inputs: BTreeMap<String, Json>,
+    /// Statuses by task.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    status: BTreeMap<String, TaskStatus>,
+}
+
+fn num() -> InputKind {
+    InputKind::Num
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// Reads a competition from JSON.
+pub fn from_str(s: &str) -> Result<Competition, LoadError> {
+    let file: CompetitionFile = serde_json::from_str(s)?;
+    let mut c = Competition::new(&file.name);
+    for sf in file.series {
+        let mut series = Series::new(&sf.name);
+        series.tie_breaks = sf.tie_breaks;
+        for tf in sf.tasks {
+            let mut task = Task::new(&tf.name);
+            for of in tf.subtasks {
+                task.subtasks.push(Subtask {
+                    name: of.name,
+                    formula: of.formula,
+                    inputs: of.inputs
+                        .into_iter()
+                        .map(|i| InputDef {
+                            name: i.name,
+                            kind: i.kind,
+                        })
+                        .collect(),
+                });
+            }
+            series.tasks.push(task);
+        }
+        for tf in sf.teams {
+            if series.find_team(tf.number).is_some() {
+                return Err(LoadError::Invalid(format!(
+                    "team {} twice in series {}",
+                    tf.number, series.name
+                )));
+            }
+            let mut team = Team::new(tf.number, &tf.name);
+            team.participating = tf.participating;
+            let number = tf.number;
+            for (key, v) in tf.inputs {
+                let invalid = |m: String| {
+                    LoadError::Invalid(format!("input {} of team {}: {}", key, number, m))
+                };
+                let id = series.find_key(&key).ok_or_else(|| invalid("no such input".into()))?;
+                let kind = series.tasks[id.task].subtasks[id.subtask].inputs[id.input].kind;
+                let v = match (kind, v) {
+                    (_, Json::Null) => Value::Empty,
+                    (InputKind::Num, Json::Number(n)) => Value::Num(n.as_f64().unwrap_or(0.0)),
+                    (InputKind::Time, Json::String(s)) => kind.parse(&s).map_err(&invalid)?,
+                    (InputKind::Num, _) => return Err(invalid("expected a number".into())),
+                    (InputKind::Time, _) => return Err(invalid("expected a time as \"hh:mm:ss\"".into())),
+                };
+                team.set(id, v);
+            }
+            for (task, status) in tf.status {
+                let t = series.find_task(&task).ok_or_else(|| {
+                    LoadError::Invalid(format!("status of team {}: no task {}", number, task))
+                })?;
+                team.statuses.insert(t, status);
+            }
+            series.teams.push(team);
+        }
+        c.series.push(series);
+    }
+    Ok(c)
+}
+
+/// Reads a competition from a JSON file.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Competition, LoadError> {
+    from_str(&fs::read_to_string(path)?)
+}
+
+/// Writes a competition as pretty printed JSON. Reading it back with
+/// `from_str` gives the same competition.
+pub fn to_string(c: &Competition) -> String {
+    let file = CompetitionFile {
+        name: c.name.clone(),
+        series: c.series.iter().map(series_file).collect(),
+    };
+    serde_json::to_string_pretty(&file).unwrap_or_default()
+}
+
+fn series_file(s: &Series) -> SeriesFile {
+    SeriesFile {
+        name: s.name.clone(),
+        tasks: s.tasks
+            .iter()
+            .map(|t| TaskFile {
+                name: t.name.clone(),
+                subtasks: t.subtasks
+                    .iter()
+                    .map(|o| SubtaskFile {
+                        name: o.name.clone(),
+                        formula: o.formula.clone(),
+                        inputs: o.inputs
+                            .iter()
+                            .map(|i| InputFile {
+                                name: i.name.clone(),
+                                kind: i.kind,
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        teams: s.teams
+            .iter()
+            .map(|t| TeamFile {
+                number: t.number,
+                name: t.name.clone(),
+                participating: t.participating,
+                inputs: s.input_ids()
+                    .into_iter()
+                    .filter_map(|id| {
+                        let v = match t.value(id) {
+                            Value::Time(_) => Json::String(t.value(id).to_string()),
+                            v => Json::from(v.as_num()?),
+                        };
+                        Some((s.input_key(id), v))
+                    })
+                    .collect(),
+                status: t.statuses
+                    .iter()
+                    .map(|(task, status)| (s.tasks[*task].name.clone(), *status))
+                    .collect(),
+            })
+            .collect(),
+        tie_breaks: s.tie_breaks.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../../doc/competition.json");
+
+    #[test]
+    fn test_example() {
+        let c = from_str(EXAMPLE).unwrap();
+        let s = &c.series[0];
+        assert_eq!(3, s.teams.len());
+        assert!(!s.teams[2].participating);
+        assert_eq!(vec![TieBreak::Task("suunnistus".into()), TieBreak::BestPlacements], s.tie_breaks);
+        let id = s.find_key("suunnistus.aika.maali").unwrap();
+        assert_eq!(Value::Time(38730.0), s.teams[0].value(id));
+        let points: Vec<Option<f64>> = c.calculate()
+            .into_iter()
+            .filter(|p| p.task == 0)
+            .map(|p| p.points.unwrap())
+            .collect();
+        assert_eq!(vec![Some(10.0), Some(5.0), None], points);
+    }
+    #[test]
+    fn test_roundtrip() {
+        let c = from_str(EXAMPLE).unwrap();
+        assert_eq!(c, from_str(&to_string(&c)).unwrap());
+    }
+    #[test]
+    fn test_invalid() {
+        let invalid = |s: &str| match from_str(s) {
+            Err(LoadError::Invalid(m)) => m,
+            r => panic!("{:?}", r),
+        };
+        let team = |inputs: &str| {
+            format!(
+                r#"{{"name": "k", "series": [{{"name": "s", "tasks": [{{"name": "t", "subtasks": [
+                   {{"name": "o", "formula": "a", "inputs": [{{"name": "a"}}, {{"name": "b", "kind": "time"}}]}}]}}],
+                   "teams": [{{"number": 1, "inputs": {}}}]}}]}}"#,
+                inputs
+            )
+        };
+        assert!(from_str(&team(r#"{"t.o.a": 1, "t.o.b": "12:00", "t.o.c": null}"#)).is_err());
+        assert_eq!("input t.o.x of team 1: no such input", invalid(&team(r#"{"t.o.x": 1}"#)));
+        assert_eq!("input t.o.a of team 1: expected a number", invalid(&team(r#"{"t.o.a": "1"}"#)));
+        assert_eq!("input t.o.b of team 1: 25:00 is not a time", invalid(&team(r#"{"t.o.b": "25:00"}"#)));
+        let status = team(r#"{}, "status": {"x": "disqualified"}"#);
+        assert_eq!("status of team 1: no task x", invalid(&status));
+        assert!(from_str(&team(r#"{"t.o.a": 1, "t.o.b": "12:00"}"#)).is_ok());
+        match from_str("{") {
+            Err(LoadError::Json(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+}
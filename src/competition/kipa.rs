@@ -0,0 +1,484 @@
+//! Imports competitions from a Kipa database dump. Kipa is a Django
+//! application, so its database is dumped as JSON with
+//! `python manage.py dumpdata tupa > kipa.json`, or as SQL with
+//! `sqlite3 kipa.db .dump > kipa.sql` or `mysqldump kipa > kipa.sql`. Both
+//! are read, see `sqldump` for what is understood of SQL.
+//!
+//! The dump is a list of objects `{"model": ..., "pk": ..., "fields": {...}}`.
+//! A row of table `tupa_<model>` of an SQL dump is read as object
+//! `tupa.<model>` with its column `id` as the key and columns `<field>_id`
+//! as fields `<field>`. These models are read, everything else is skipped:
+//!
+//! ```text
+//! tupa.kilpailu       competition        nimi
+//! tupa.sarja          series             nimi, kilpailu
+//! tupa.tehtava        task               nimi, sarja, jarjestysnro
+//! tupa.osatehtava     subtask            nimi, kaava, tehtava
+//! tupa.syotemaarite   input definition   nimi, tyyppi, osa_tehtava
+//! tupa.vartio         team               nro, nimi, sarja, ulkopuolella
+//! tupa.syote          input value        arvo, maarite, vartio
+//! tupa.testaustulos   points of a task   pisteet, tehtava, vartio
+//! ```
+//!
+//! Teams with `ulkopuolella` set take no part in the competition. Inputs of
+//! type `aika` are clock times, others numbers. The points Kipa has stored
+//! for the tasks are kept apart from the competition, so that `compare` can
+//! check them against the points of Kilac.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde_json;
+use serde_json::{Map, Value as Json};
+
+use calc::Value;
+
+use super::error::LoadError;
+use super::results::score;
+use super::sqldump;
+use super::sqldump::Row;
+use super::{Competition, InputDef, InputId, InputKind, Series, Subtask, Task, Team};
+
+#[derive(Debug, Deserialize)]
+struct Object {
+    model: String,
+    pk: Json,
+    #[serde(default)]
+    fields: Map<String, Json>,
+}
+
+impl Object {
+    fn pk(&self) -> i64 {
+        self.pk.as_i64().unwrap_or(0)
+    }
+
+    fn invalid(&self, m: &str) -> LoadError {
+        LoadError::Invalid(format!("{} {}: {}", self.model, self.pk, m))
+    }
+
+    /// Text of the first of `names` that is given, empty if none.
+    fn text(&self, names: &[&str]) -> String {
+        match names.iter().filter_map(|n| self.fields.get(*n)).next() {
+            Some(Json::String(s)) => s.clone(),
+            Some(Json::Number(n)) => n.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Primary key of the object field `names` refers to.
+    fn fk(&self, names: &[&str]) -> Result<i64, LoadError> {
+        names
+            .iter()
+            .filter_map(|n| self.fields.get(*n))
+            .filter_map(Json::as_i64)
+            .next()
+            .ok_or_else(|| self.invalid(&format!("missing {}", names[0])))
+    }
+}
+
+/// Points of task `task` of team `team` of series `series` stored by Kipa,
+/// `None` if it stored none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stored {
+    pub series: usize,
+    pub task: usize,
+    pub team: usize,
+    pub points: Option<f64>,
+}
+
+/// Competition of a Kipa dump and the points Kipa has stored for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dump {
+    pub competition: Competition,
+    pub stored: Vec<Stored>,
+}
+
+/// Points of a task of a team that differ between Kipa and Kilac.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub series: usize,
+    pub task: usize,
+    pub team: usize,
+    pub kipa: Option<f64>,
+    pub kilac: Option<f64>,
+}
+
+/// Reads every competition of a Kipa dump, in the order of their keys.
+pub fn from_str(s: &str) -> Result<Vec<Competition>, LoadError> {
+    Ok(read(s)?.into_iter().map(|d| d.competition).collect())
+}
+
+/// Reads every competition of a Kipa dump with the points stored for it.
+pub fn read(s: &str) -> Result<Vec<Dump>, LoadError> {
+    let objects: Vec<Object> = if is_sql(s) {
+        sqldump::rows(s)?.into_iter().filter_map(object).collect()
+    } else {
+        serde_json::from_str(s)?
+    };
+    let mut models: HashMap<String, Vec<Object>> = HashMap::new();
+    for o in objects {
+        models.entry(o.model.to_lowercase()).or_default().push(o);
+    }
+    let mut take = |m: &str, order: &str| {
+        let mut v = models.remove(m).unwrap_or_default();
+        v.sort_by_key(|o| (o.fields.get(order).and_then(Json::as_i64), o.pk()));
+        v
+    };
+    let mut competitions: BTreeMap<i64, Competition> = BTreeMap::new();
+    for o in take("tupa.kilpailu", "") {
+        competitions.insert(o.pk(), Competition::new(&o.text(&["nimi"])));
+    }
+    // Indices of series, tasks, subtasks and teams by their keys.
+    let mut series: HashMap<i64, (i64, usize)> = HashMap::new();
+    for o in take("tupa.sarja", "") {
+        let c = competitions
+            .get_mut(&o.fk(&["kilpailu"])?)
+            .ok_or_else(|| o.invalid("no such kilpailu"))?;
+        series.insert(o.pk(), (o.fk(&["kilpailu"])?, c.series.len()));
+        c.series.push(Series::new(&o.text(&["nimi"])));
+    }
+    let mut tasks: HashMap<i64, (i64, usize)> = HashMap::new();
+    for o in take("tupa.tehtava", "jarjestysnro") {
+        let pk = o.fk(&["sarja"])?;
+        let s = series_mut(&mut competitions, &series, &o, pk)?;
+        tasks.insert(o.pk(), (pk, s.tasks.len()));
+        s.tasks.push(Task::new(&o.text(&["nimi"])));
+    }
+    let mut subtasks: HashMap<i64, (i64, usize, usize)> = HashMap::new();
+    for o in take("tupa.osatehtava", "") {
+        let (spk, t) = *tasks
+            .get(&o.fk(&["tehtava"])?)
+            .ok_or_else(|| o.invalid("no such tehtava"))?;
+        let task = &mut series_mut(&mut competitions, &series, &o, spk)?.tasks[t];
+        subtasks.insert(o.pk(), (spk, t, task.subtasks.len()));
+        task.subtasks.push(Subtask {
+            name: o.text(&["nimi"]),
+            formula: o.text(&["kaava"]),
+            inputs: Vec::new(),
+        });
+    }
+    let mut inputs: HashMap<i64, (i64, InputId, InputKind)> = HashMap::new();
+    for o in take("tupa.syotemaarite", "") {
+        let (spk, t, st) = *subtasks
+            .get(&o.fk(&["osa_tehtava", "osatehtava"])?)
+            .ok_or_else(|| o.invalid("no such osatehtava"))?;
+        let sub = &mut series_mut(&mut competitions, &series, &o, spk)?.tasks[t].subtasks[st];
+        let kind = if o.text(&["tyyppi"]) == "aika" { InputKind::Time } else { InputKind::Num };
+        let id = InputId {
+            task: t,
+            subtask: st,
+            input: sub.inputs.len(),
+        };
+        inputs.insert(o.pk(), (spk, id, kind));
+        sub.inputs.push(InputDef {
+            name: o.text(&["nimi"]),
+            kind,
+        });
+    }
+    let mut teams: HashMap<i64, (i64, usize)> = HashMap::new();
+    for o in take("tupa.vartio", "nro") {
+        let spk = o.fk(&["sarja"])?;
+        let s = series_mut(&mut competitions, &series, &o, spk)?;
+        let number = o.text(&["nro"]).parse().map_err(|_| o.invalid("nro is not a number"))?;
+        let mut team = Team::new(number, &o.text(&["nimi"]));
+        team.participating = !o.fields.get("ulkopuolella").map(truthy).unwrap_or(false);
+        teams.insert(o.pk(), (spk, s.teams.len()));
+        s.teams.push(team);
+    }
+    for o in take("tupa.syote", "") {
+        let (ispk, id, kind) = *inputs
+            .get(&o.fk(&["maarite"])?)
+            .ok_or_else(|| o.invalid("no such syotemaarite"))?;
+        let (tspk, team) = *teams
+            .get(&o.fk(&["vartio"])?)
+            .ok_or_else(|| o.invalid("no such vartio"))?;
+        if ispk != tspk {
+            return Err(o.invalid("vartio and syotemaarite are in different series"));
+        }
+        let v = value(kind, &o.text(&["arvo"])).map_err(|e| o.invalid(&e))?;
+        series_mut(&mut competitions, &series, &o, tspk)?.teams[team].set(id, v);
+    }
+    let mut stored: BTreeMap<i64, Vec<Stored>> = BTreeMap::new();
+    for o in take("tupa.testaustulos", "") {
+        let (spk, task) = *tasks
+            .get(&o.fk(&["tehtava"])?)
+            .ok_or_else(|| o.invalid("no such tehtava"))?;
+        let (tspk, team) = *teams
+            .get(&o.fk(&["vartio"])?)
+            .ok_or_else(|| o.invalid("no such vartio"))?;
+        if spk != tspk {
+            return Err(o.invalid("vartio and tehtava are in different series"));
+        }
+        let points = match o.text(&["pisteet"]).trim().replace(',', ".") {
+            ref p if p.is_empty() => None,
+            p => Some(p.parse().map_err(|_| o.invalid("pisteet is not a number"))?),
+        };
+        let (cpk, series) = series[&spk];
+        stored.entry(cpk).or_default().push(Stored {
+            series,
+            task,
+            team,
+            points,
+        });
+    }
+    Ok(competitions
+        .into_iter()
+        .map(|(pk, competition)| Dump {
+            competition,
+            stored: stored.remove(&pk).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Whether `s` is an SQL dump. A JSON dump is a list.
+fn is_sql(s: &str) -> bool {
+    !s.trim_start().starts_with('[')
+}
+
+/// Row of a table of Kipa as the object Django would dump it as. Rows of
+/// other tables are skipped.
+fn object(row: Row) -> Option<Object> {
+    if !row.table.to_lowercase().starts_with("tupa_") {
+        return None;
+    }
+    let mut pk = Json::Null;
+    let mut fields = Map::new();
+    for (k, v) in row.values {
+        if k == "id" {
+            pk = v;
+        } else if k.ends_with("_id") {
+            fields.insert(k[..k.len() - 3].to_string(), v);
+        } else {
+            fields.insert(k, v);
+        }
+    }
+    Some(Object {
+        model: format!("tupa.{}", &row.table[5..]),
+        pk,
+        fields,
+    })
+}
+
+/// Calculates the competition of `dump` and lists the tasks of teams whose
+/// points differ from the points Kipa has stored, in the order of series,
+/// task and team. Only tasks with stored points are compared.
+pub fn compare(dump: &Dump) -> Vec<Difference> {
+    let results = score(&dump.competition, &dump.competition.calculate());
+    let mut res: Vec<Difference> = dump
+        .stored
+        .iter()
+        .filter_map(|s| {
+            let kilac = results[s.series]
+                .standings
+                .iter()
+                .find(|st| st.team == s.team)
+                .and_then(|st| st.tasks[s.task]);
+            let same = match (s.points, kilac) {
+                (Some(a), Some(b)) => (a - b).abs() < 1e-6,
+                (a, b) => a == b,
+            };
+            if same {
+                return None;
+            }
+            Some(Difference {
+                series: s.series,
+                task: s.task,
+                team: s.team,
+                kipa: s.points,
+                kilac,
+            })
+        })
+        .collect();
+    res.sort_by_key(|d| (d.series, d.task, d.team));
+    res
+}
+
+/// Whether a flag of Kipa is set. Kipa stores flags as booleans or
+/// integers, depending on the database, and may leave them null.
+fn truthy(v: &Json) -> bool {
+    match *v {
+        Json::Null => false,
+        Json::Bool(b) => b,
+        Json::Number(ref n) => n.as_f64().map(|n| n != 0.0).unwrap_or(false),
+        Json::String(ref s) => !(s.is_empty() || s == "0" || s.eq_ignore_ascii_case("false")),
+        Json::Array(ref a) => !a.is_empty(),
+        Json::Object(ref o) => !o.is_empty(),
+    }
+}
+
+/// Series with key `pk`, which object `o` refers to.
+fn series_mut<'a>(
+    competitions: &'a mut BTreeMap<i64, Competition>,
+    series: &HashMap<i64, (i64, usize)>,
+    o: &Object,
+    pk: i64,
+) -> Result<&'a mut Series, LoadError> {
+    let (c, s) = *series.get(&pk).ok_or_else(|| o.invalid("no such sarja"))?;
+    Ok(&mut competitions.get_mut(&c).ok_or_else(|| o.invalid("no such kilpailu"))?.series[s])
+}
+
+/// Reads every competition of a Kipa dump file.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Competition>, LoadError> {
+    from_str(&fs::read_to_string(path)?)
+}
+
+/// Reads every competition of a Kipa dump file with the points stored for
+/// it.
+pub fn load_dump<P: AsRef<Path>>(path: P) -> Result<Vec<Dump>, LoadError> {
+    read(&fs::read_to_string(path)?)
+}
+
+/// Kipa stores inputs as text. Numbers may have a decimal comma and times
+/// may be seconds.
+fn value(kind: InputKind, s: &str) -> Result<Value, String> {
+    let s = s.trim().replace(',', ".");
+    match (kind, s.parse::<f64>()) {
+        (InputKind::Time, Ok(secs)) => Ok(Value::Time(secs)),
+        _ => kind.parse(&s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = r#"[
+        {"model": "tupa.kilpailu", "pk": 1, "fields": {"nimi": "Kisa"}},
+        {"model": "tupa.sarja", "pk": 3, "fields": {"nimi": "Sudenpennut", "kilpailu": 1}},
+        {"model": "tupa.tehtava", "pk": 8, "fields": {"nimi": "ensiapu", "sarja": 3, "jarjestysnro": 2}},
+        {"model": "tupa.tehtava", "pk": 9, "fields": {"nimi": "start", "sarja": 3, "jarjestysnro": 1}},
+        {"model": "tupa.osatehtava", "pk": 20, "fields": {"nimi": "a", "kaava": "a/max(.a*muk)*10", "tehtava": 8}},
+        {"model": "tupa.osatehtava", "pk": 21, "fields": {"nimi": "b", "kaava": "aikavali(l, m)", "tehtava": 9}},
+        {"model": "tupa.syotemaarite", "pk": 30, "fields": {"nimi": "a", "tyyppi": "kokonaisluku", "osa_tehtava": 20}},
+        {"model": "tupa.syotemaarite", "pk": 31, "fields": {"nimi": "l", "tyyppi": "aika", "osa_tehtava": 21}},
+        {"model": "tupa.syotemaarite", "pk": 32, "fields": {"nimi": "m", "tyyppi": "aika", "osa_tehtava": 21}},
+        {"model": "tupa.vartio", "pk": 41, "fields": {"nro": 2, "nimi": "Ketut", "sarja": 3, "ulkopuolella": 1}},
+        {"model": "tupa.vartio", "pk": 40, "fields": {"nro": 1, "nimi": "Kotkat", "sarja": 3, "ulkopuolella": null}},
+        {"model": "tupa.vartio", "pk": 42, "fields": {"nro": 3, "nimi": "Karhut", "sarja": 3, "ulkopuolella": false}},
+        {"model": "tupa.vartio", "pk": 43, "fields": {"nro": 4, "nimi": "Hirvet", "sarja": 3, "ulkopuolella": 0}},
+        {"model": "tupa.vartio", "pk": 44, "fields": {"nro": 5, "nimi": "Ilvekset", "sarja": 3, "ulkopuolella": true}},
+        {"model": "tupa.syote", "pk": 50, "fields": {"arvo": "7,5", "maarite": 30, "vartio": 40}},
+        {"model": "tupa.syote", "pk": 51, "fields": {"arvo": "9", "maarite": 30, "vartio": 41}},
+        {"model": "tupa.syote", "pk": 52, "fields": {"arvo": "10:00", "maarite": 31, "vartio": 40}},
+        {"model": "tupa.syote", "pk": 53, "fields": {"arvo": "37800", "maarite": 32, "vartio": 40}},
+        {"model": "tupa.syote", "pk": 54, "fields": {"arvo": "", "maarite": 32, "vartio": 41}},
+        {"model": "tupa.testaustulos", "pk": 60, "fields": {"pisteet": "10", "tehtava": 8, "vartio": 40}},
+        {"model": "tupa.testaustulos", "pk": 61, "fields": {"pisteet": "12,0", "tehtava": 8, "vartio": 41}},
+        {"model": "tupa.testaustulos", "pk": 62, "fields": {"pisteet": "1700", "tehtava": 9, "vartio": 40}},
+        {"model": "tupa.testaustulos", "pk": 63, "fields": {"pisteet": "5", "tehtava": 9, "vartio": 41}},
+        {"model": "auth.user", "pk": 1, "fields": {"username": "admin"}}
+    ]"#;
+
+    #[test]
+    fn test_import() {
+        let c = from_str(DUMP).unwrap();
+        assert_eq!(1, c.len());
+        let s = &c[0].series[0];
+        assert_eq!("Sudenpennut", s.name);
+        let names: Vec<&str> = s.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(vec!["start", "ensiapu"], names);
+        assert_eq!((1, true), (s.teams[0].number, s.teams[0].participating));
+        assert_eq!((2, false), (s.teams[1].number, s.teams[1].participating));
+        // Flags may be booleans or integers.
+        assert_eq!((3, true), (s.teams[2].number, s.teams[2].participating));
+        assert_eq!((4, true), (s.teams[3].number, s.teams[3].participating));
+        assert_eq!((5, false), (s.teams[4].number, s.teams[4].participating));
+        let a = s.find_key("ensiapu.a.a").unwrap();
+        assert_eq!(Value::Num(7.5), s.teams[0].value(a));
+        assert_eq!(Value::Time(37800.0), s.teams[0].value(s.find_key("start.b.m").unwrap()));
+        let points: Vec<Option<f64>> = c[0]
+            .calculate()
+            .into_iter()
+            .filter(|p| p.team < 2)
+            .map(|p| p.points.unwrap())
+            .collect();
+        // Ketut take no part, so Kotkat have the best result.
+        assert_eq!(vec![Some(1800.0), None, Some(10.0), Some(12.0)], points);
+    }
+    #[test]
+    fn test_compare() {
+        let d = read(DUMP).unwrap();
+        assert_eq!(4, d[0].stored.len());
+        assert_eq!(
+            vec![
+                Difference {
+                    series: 0,
+                    task: 0,
+                    team: 0,
+                    kipa: Some(1700.0),
+                    kilac: Some(1800.0),
+                },
+                Difference {
+                    series: 0,
+                    task: 0,
+                    team: 1,
+                    kipa: Some(5.0),
+                    kilac: None,
+                },
+            ],
+            compare(&d[0])
+        );
+    }
+    #[test]
+    fn test_invalid() {
+        let dump = r#"[{"model": "tupa.sarja", "pk": 3, "fields": {"nimi": "S", "kilpailu": 2}}]"#;
+        assert_eq!(
+            "invalid competition: tupa.sarja 3: no such kilpailu",
+            from_str(dump).unwrap_err().to_string()
+        );
+        let sql = "CREATE TABLE tupa_sarja (id integer, nimi text, kilpailu_id integer);\n\
+                   INSERT INTO tupa_sarja VALUES(3,'S',2);\n";
+        assert_eq!(
+            "invalid competition: tupa.sarja 3: no such kilpailu",
+            from_str(sql).unwrap_err().to_string()
+        );
+    }
+    #[test]
+    fn test_sql() {
+        // DUMP as `sqlite3 .dump` writes it.
+        let sql = "PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\n\
+            CREATE TABLE \"tupa_kilpailu\" (\"id\" integer NOT NULL PRIMARY KEY, \"nimi\" varchar(255) NOT NULL);\n\
+            INSERT INTO tupa_kilpailu VALUES(1,'Kisa');\n\
+            CREATE TABLE \"tupa_sarja\" (\"id\" integer NOT NULL PRIMARY KEY, \"nimi\" varchar(255), \
+            \"kilpailu_id\" integer NOT NULL REFERENCES \"tupa_kilpailu\" (\"id\"));\n\
+            INSERT INTO tupa_sarja VALUES(3,'Sudenpennut',1);\n\
+            CREATE TABLE \"tupa_tehtava\" (\"id\" integer, \"nimi\" varchar(255), \"sarja_id\" integer, \
+            \"jarjestysnro\" integer);\n\
+            INSERT INTO tupa_tehtava VALUES(8,'ensiapu',3,2);\n\
+            INSERT INTO tupa_tehtava VALUES(9,'start',3,1);\n\
+            CREATE TABLE \"tupa_osatehtava\" (\"id\" integer, \"nimi\" varchar(255), \"kaava\" text, \
+            \"tehtava_id\" integer);\n\
+            INSERT INTO tupa_osatehtava VALUES(20,'a','a/max(.a*muk)*10',8);\n\
+            INSERT INTO tupa_osatehtava VALUES(21,'b','aikavali(l, m)',9);\n\
+            CREATE TABLE \"tupa_syotemaarite\" (\"id\" integer, \"nimi\" varchar(255), \"tyyppi\" varchar(255), \
+            \"osa_tehtava_id\" integer);\n\
+            INSERT INTO tupa_syotemaarite VALUES(30,'a','kokonaisluku',20);\n\
+            INSERT INTO tupa_syotemaarite VALUES(31,'l','aika',21);\n\
+            INSERT INTO tupa_syotemaarite VALUES(32,'m','aika',21);\n\
+            CREATE TABLE \"tupa_vartio\" (\"id\" integer, \"nro\" integer, \"nimi\" varchar(255), \
+            \"sarja_id\" integer, \"ulkopuolella\" integer NULL);\n\
+            INSERT INTO tupa_vartio VALUES(41,2,'Ketut',3,1);\n\
+            INSERT INTO tupa_vartio VALUES(40,1,'Kotkat',3,NULL);\n\
+            INSERT INTO tupa_vartio VALUES(42,3,'Karhut',3,0);\n\
+            INSERT INTO tupa_vartio VALUES(43,4,'Hirvet',3,0);\n\
+            INSERT INTO tupa_vartio VALUES(44,5,'Ilvekset',3,1);\n\
+            CREATE TABLE \"tupa_syote\" (\"id\" integer, \"arvo\" varchar(255), \"maarite_id\" integer, \
+            \"vartio_id\" integer);\n\
+            INSERT INTO tupa_syote VALUES(50,'7,5',30,40);\n\
+            INSERT INTO tupa_syote VALUES(51,'9',30,41);\n\
+            INSERT INTO tupa_syote VALUES(52,'10:00',31,40);\n\
+            INSERT INTO tupa_syote VALUES(53,'37800',32,40);\n\
+            INSERT INTO tupa_syote VALUES(54,'',32,41);\n\
+            CREATE TABLE \"tupa_testaustulos\" (\"id\" integer, \"pisteet\" varchar(255), \"tehtava_id\" integer, \
+            \"vartio_id\" integer);\n\
+            INSERT INTO tupa_testaustulos VALUES(60,'10',8,40);\n\
+            INSERT INTO tupa_testaustulos VALUES(61,'12,0',8,41);\n\
+            INSERT INTO tupa_testaustulos VALUES(62,'1700',9,40);\n\
+            INSERT INTO tupa_testaustulos VALUES(63,'5',9,41);\n\
+            CREATE TABLE \"auth_user\" (\"id\" integer, \"username\" varchar(30));\n\
+            INSERT INTO auth_user VALUES(1,'admin');\n\
+            COMMIT;\n";
+        assert_eq!(read(DUMP).unwrap(), read(sql).unwrap());
+    }
+}
@@ -0,0 +1,351 @@
+//! In-memory model of a competition. A competition has series, a series
+//! has tasks and teams, a task has subtasks and a subtask has the
+//! definitions of the inputs its formula is calculated from. Every team
+//! has its own values for the inputs of its series.
+//!
+//! Things are referred to by their name in formulas and by their index in
+//! code. See `ctx::CompetitionCtx` for evaluating formulas against a model,
+//! `results` for ranking the teams and `export` for publishing the results,
+//! `json` for storing a competition in a file and `sqlite` for storing one
+//! in a database.
+
+pub mod csv;
+pub mod ctx;
+pub mod error;
+pub mod export;
+pub mod json;
+pub mod kipa;
+pub mod results;
+pub mod sqldump;
+pub mod sqlite;
+
+use std::collections::HashMap;
+
+use calc::error::KilaError;
+use calc::lexer::{lex, Token};
+use calc::{calculate_err, Value};
+
+use self::ctx::CompetitionCtx;
+use self::results::SeriesResults;
+
+/// The whole competition ("kilpailu").
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Competition {
+    pub name: String,
+    pub series: Vec<Series>,
+}
+
+/// Series ("sarja") of the competition. Teams of a series do the same tasks
+/// and are ranked against each other.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Series {
+    pub name: String,
+    pub tasks: Vec<Task>,
+    pub teams: Vec<Team>,
+    /// How teams with the same total are ordered, tried in order.
+    pub tie_breaks: Vec<TieBreak>,
+}
+
+/// Rule for ordering teams with the same total, see `results::score`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    /// More points from the task called this wins ("kärkitehtävä").
+    Task(String),
+    /// More first places in tasks wins, then more second places and so on.
+    BestPlacements,
+    /// The teams share the place. Rules after this are never tried.
+    Shared,
+}
+
+/// Task ("tehtävä") of a series.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Task {
+    pub name: String,
+    pub subtasks: Vec<Subtask>,
+}
+
+/// Subtask ("osatehtävä"). Its formula gives the points of a team from the
+/// inputs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Subtask {
+    pub name: String,
+    pub formula: String,
+    pub inputs: Vec<InputDef>,
+}
+
+/// Definition of an input ("syötemäärite") of a subtask.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputDef {
+    pub name: String,
+    pub kind: InputKind,
+}
+
+/// What kind of values an input takes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputKind {
+    Num,
+    /// Clock time of the day.
+    Time,
+}
+
+/// Points at an input definition of a series by indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InputId {
+    pub task: usize,
+    pub subtask: usize,
+    pub input: usize,
+}
+
+/// Team ("vartio") of a series.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Team {
+    pub number: u32,
+    pub name: String,
+    /// Teams that do not take part in the competition are scored but left
+    /// out of `muk` and `..mukana`.
+    pub participating: bool,
+    /// Inputs given for the team. Inputs not given are empty.
+    pub inputs: HashMap<InputId, Value>,
+    /// Statuses of the team in tasks, by index of the task.
+    pub statuses: HashMap<usize, TaskStatus>,
+}
+
+/// Status of a team in a task, set by the judges.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    /// Disqualified ("hylätty"). The team gets zero points from the task and
+    /// its inputs of the task are left empty in the inputs of other teams.
+    Disqualified,
+    /// Penalty points ("sakko") taken from the points of the task.
+    Penalty(f64),
+}
+
+/// Points of a team from a subtask, see `Competition::calculate`. Points
+/// are `None` if the formula has no value because of missing inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Points {
+    pub series: usize,
+    pub task: usize,
+    pub subtask: usize,
+    /// Index of the team in the series.
+    pub team: usize,
+    pub points: Result<Option<f64>, KilaError>,
+}
+
+impl InputKind {
+    /// Parses an input written as text: a number or a clock time `hh:mm` or
+    /// `hh:mm:ss`. Empty text is an empty input.
+    pub fn parse(&self, s: &str) -> Result<Value, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(Value::Empty);
+        }
+        match *self {
+            InputKind::Num => s.parse().map(Value::Num).map_err(|_| format!("{} is not a number", s)),
+            InputKind::Time => match lex(s).ok().as_ref().map(|t| &t[..]) {
+                Some([t]) => match t.node {
+                    Token::Time(secs) => Ok(Value::Time(secs)),
+                    _ => Err(format!("{} is not a time", s)),
+                },
+                _ => Err(format!("{} is not a time", s)),
+            },
+        }
+    }
+}
+
+impl Competition {
+    pub fn new(name: &str) -> Competition {
+        Competition {
+            name: name.into(),
+            series: Vec::new(),
+        }
+    }
+
+    /// Index of the series called `name`.
+    pub fn find_series(&self, name: &str) -> Option<usize> {
+        self.series.iter().position(|s| s.name == name)
+    }
+
+    /// Calculates the formula of every subtask for every team of its series.
+    /// Teams disqualified from a task get zero points from its subtasks.
+    pub fn calculate(&self) -> Vec<Points> {
+        let mut res = Vec::new();
+        for (si, s) in self.series.iter().enumerate() {
+            for (ti, t) in s.tasks.iter().enumerate() {
+                for (oi, o) in t.subtasks.iter().enumerate() {
+                    for team in 0..s.teams.len() {
+                        let points = if s.teams[team].disqualified(ti) {
+                            Ok(Some(0.0))
+                        } else {
+                            calculate_err(o.formula.clone(), CompetitionCtx::new(self, si, ti, oi, team))
+                        };
+                        res.push(Points {
+                            series: si,
+                            task: ti,
+                            subtask: oi,
+                            team,
+                            points,
+                        });
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Calculates the competition and ranks the teams of every series, see
+    /// `results::score`.
+    pub fn results(&self) -> Vec<SeriesResults> {
+        results::score(self, &self.calculate())
+    }
+}
+
+impl Series {
+    pub fn new(name: &str) -> Series {
+        Series {
+            name: name.into(),
+            ..Series::default()
+        }
+    }
+
+    /// Index of the task called `name`.
+    pub fn find_task(&self, name: &str) -> Option<usize> {
+        self.tasks.iter().position(|t| t.name == name)
+    }
+
+    /// Looks up input `input` of subtask `subtask` of task `task` by names.
+    pub fn find_input(&self, task: &str, subtask: &str, input: &str) -> Option<InputId> {
+        let t = self.find_task(task)?;
+        let s = self.tasks[t].find_subtask(subtask)?;
+        self.input_in(t, s, input)
+    }
+
+    /// Looks up input called `input` of a subtask given by indices.
+    pub fn input_in(&self, task: usize, subtask: usize, input: &str) -> Option<InputId> {
+        let s = self.tasks.get(task)?.subtasks.get(subtask)?;
+        let i = s.inputs.iter().position(|i| i.name == input)?;
+        Some(InputId {
+            task,
+            subtask,
+            input: i,
+        })
+    }
+
+    /// Name of an input as written in files, `task.subtask.input`.
+    pub fn input_key(&self, id: InputId) -> String {
+        let t = &self.tasks[id.task];
+        let s = &t.subtasks[id.subtask];
+        format!("{}.{}.{}", t.name, s.name, s.inputs[id.input].name)
+    }
+
+    /// Looks up an input by its name in files, see `input_key`.
+    pub fn find_key(&self, key: &str) -> Option<InputId> {
+        let p: Vec<&str> = key.split('.').collect();
+        match p[..] {
+            [t, s, i] => self.find_input(t, s, i),
+            _ => None,
+        }
+    }
+
+    /// Every input of the series in order of tasks and subtasks.
+    pub fn input_ids(&self) -> Vec<InputId> {
+        let mut res = Vec::new();
+        for (ti, t) in self.tasks.iter().enumerate() {
+            for (si, s) in t.subtasks.iter().enumerate() {
+                for i in 0..s.inputs.len() {
+                    res.push(InputId {
+                        task: ti,
+                        subtask: si,
+                        input: i,
+                    });
+                }
+            }
+        }
+        res
+    }
+
+    /// Index of the team with number `number`.
+    pub fn find_team(&self, number: u32) -> Option<usize> {
+        self.teams.iter().position(|t| t.number == number)
+    }
+
+    /// Values of input `id` for every team of the series, in the order of
+    /// the teams.
+    pub fn values(&self, id: InputId) -> Vec<Value> {
+        self.teams.iter().map(|t| t.value(id)).collect()
+    }
+}
+
+impl Task {
+    pub fn new(name: &str) -> Task {
+        Task {
+            name: name.into(),
+            subtasks: Vec::new(),
+        }
+    }
+
+    /// Index of the subtask called `name`.
+    pub fn find_subtask(&self, name: &str) -> Option<usize> {
+        self.subtasks.iter().position(|s| s.name == name)
+    }
+}
+
+impl Subtask {
+    /// Subtask with numeric inputs `inputs`.
+    pub fn new(name: &str, formula: &str, inputs: &[&str]) -> Subtask {
+        Subtask {
+            name: name.into(),
+            formula: formula.into(),
+            inputs: inputs
+                .iter()
+                .map(|i| InputDef {
+                    name: (*i).into(),
+                    kind: InputKind::Num,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Team {
+    /// Participating team without inputs.
+    pub fn new(number: u32, name: &str) -> Team {
+        Team {
+            number,
+            name: name.into(),
+            participating: true,
+            inputs: HashMap::new(),
+            statuses: HashMap::new(),
+        }
+    }
+
+    /// Whether the team is disqualified from task `task`.
+    pub fn disqualified(&self, task: usize) -> bool {
+        self.statuses.get(&task) == Some(&TaskStatus::Disqualified)
+    }
+
+    /// Penalty points of the team in task `task`, 0 if none.
+    pub fn penalty(&self, task: usize) -> f64 {
+        match self.statuses.get(&task) {
+            Some(TaskStatus::Penalty(p)) => *p,
+            _ => 0.0,
+        }
+    }
+
+    /// Value of input `id`, empty if not given.
+    pub fn value(&self, id: InputId) -> Value {
+        self.inputs.get(&id).cloned().unwrap_or(Value::Empty)
+    }
+
+    /// Sets input `id`. Setting it empty removes it.
+    pub fn set(&mut self, id: InputId, v: Value) {
+        if v.is_empty() {
+            self.inputs.remove(&id);
+        } else {
+            self.inputs.insert(id, v);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Results of a competition. Points of the subtasks of a task are summed to
+//! the points of the task, less the penalty of the team in the task, and
+//! points of the tasks to the total of a team.
+//! Teams of a series are ranked by their totals. Teams with the same total
+//! are ordered by the tie-break rules of the series, see `TieBreak`.
+
+use std::cmp::Ordering;
+
+use super::{Competition, Points, Series, TieBreak};
+
+/// Result of a team in its series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    /// Index of the team in the series.
+    pub team: usize,
+    /// Place of the team, `None` for teams not taking part. Teams with the
+    /// same total share a place unless a tie-break rule orders them.
+    pub rank: Option<usize>,
+    /// Points of every task. `None` if no subtask of the task has points and
+    /// the team has no penalty in it.
+    pub tasks: Vec<Option<f64>>,
+    pub total: f64,
+    /// Tie-break rule that ordered the team after the team above it with
+    /// the same total, or made them share the place. `None` if the totals
+    /// differ or no rule told the teams apart, in which case they share
+    /// the place too.
+    pub decided_by: Option<TieBreak>,
+}
+
+/// Results of a series, best team first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesResults {
+    pub series: usize,
+    pub standings: Vec<Standing>,
+    /// Subtasks whose formula failed. They give no points.
+    pub errors: Vec<Points>,
+}
+
+/// Ranks the teams of every series of `c` by `points`, which are the
+/// points of its subtasks from `Competition::calculate` or
+/// `sqlite::Db::calculate`.
+pub fn score(c: &Competition, points: &[Points]) -> Vec<SeriesResults> {
+    let mut res: Vec<SeriesResults> = c.series
+        .iter()
+        .enumerate()
+        .map(|(si, s)| SeriesResults {
+            series: si,
+            standings: (0..s.teams.len())
+                .map(|team| Standing {
+                    team,
+                    rank: None,
+                    tasks: vec![None; s.tasks.len()],
+                    total: 0.0,
+                    decided_by: None,
+                })
+                .collect(),
+            errors: Vec::new(),
+        })
+        .collect();
+    for p in points {
+        let r = &mut res[p.series];
+        match p.points {
+            Ok(Some(v)) => {
+                let task = &mut r.standings[p.team].tasks[p.task];
+                *task = Some(task.unwrap_or(0.0) + v);
+            }
+            Ok(None) => {}
+            Err(_) => r.errors.push(p.clone()),
+        }
+    }
+    for r in &mut res {
+        let series = &c.series[r.series];
+        let teams = &series.teams;
+        for st in &mut r.standings {
+            for (t, points) in st.tasks.iter_mut().enumerate() {
+                let penalty = teams[st.team].penalty(t);
+                if penalty != 0.0 {
+                    *points = Some(points.unwrap_or(0.0) - penalty);
+                }
+            }
+            st.total = st.tasks.iter().filter_map(|t| *t).sum();
+        }
+        let placements = placements(c, r);
+        let tie = |a: &Standing, b: &Standing| tie_break(series, &placements, a, b);
+        r.standings.sort_by(|a, b| {
+            let (ta, tb) = (&teams[a.team], &teams[b.team]);
+            tb.participating
+                .cmp(&ta.participating)
+                .then_with(|| b.total.partial_cmp(&a.total).unwrap_or(Ordering::Equal))
+                .then_with(|| tie(a, b).0)
+                .then_with(|| ta.number.cmp(&tb.number))
+        });
+        for i in 0..r.standings.len() {
+            if !teams[r.standings[i].team].participating {
+                continue;
+            }
+            let (rank, decided_by) = match i.checked_sub(1).map(|p| &r.standings[p]) {
+                Some(prev) if prev.total == r.standings[i].total => {
+                    match tie(prev, &r.standings[i]) {
+                        (Ordering::Equal, rule) => (prev.rank, rule.cloned()),
+                        (_, rule) => (Some(i + 1), rule.cloned()),
+                    }
+                }
+                _ => (Some(i + 1), None),
+            };
+            r.standings[i].rank = rank;
+            r.standings[i].decided_by = decided_by;
+        }
+    }
+    res
+}
+
+/// How many times every team of the series placed first, second and so
+/// on in the tasks, by index of the team. Only teams taking part are
+/// placed, by the points of the task.
+fn placements(c: &Competition, r: &SeriesResults) -> Vec<Vec<usize>> {
+    let teams = &c.series[r.series].teams;
+    let mut res = vec![vec![0; teams.len()]; teams.len()];
+    let tasks = c.series[r.series].tasks.len();
+    let points = |st: &Standing, t: usize| st.tasks[t].unwrap_or(0.0);
+    for t in 0..tasks {
+        for st in r.standings.iter().filter(|st| teams[st.team].participating) {
+            let better = r.standings
+                .iter()
+                .filter(|o| teams[o.team].participating && points(o, t) > points(st, t))
+                .count();
+            res[st.team][better] += 1;
+        }
+    }
+    res
+}
+
+/// Orders teams `a` and `b` with the same total by the tie-break rules of
+/// the series. Returns the order and the rule that decided it.
+fn tie_break<'a>(
+    series: &'a Series,
+    placements: &[Vec<usize>],
+    a: &Standing,
+    b: &Standing,
+) -> (Ordering, Option<&'a TieBreak>) {
+    for rule in &series.tie_breaks {
+        let ord = match *rule {
+            TieBreak::Task(ref name) => match series.find_task(name) {
+                Some(t) => {
+                    let (pa, pb) = (a.tasks[t].unwrap_or(0.0), b.tasks[t].unwrap_or(0.0));
+                    pb.partial_cmp(&pa).unwrap_or(Ordering::Equal)
+                }
+                None => Ordering::Equal,
+            },
+            TieBreak::BestPlacements => placements[b.team].cmp(&placements[a.team]),
+            TieBreak::Shared => return (Ordering::Equal, Some(rule)),
+        };
+        if ord != Ordering::Equal {
+            return (ord, Some(rule));
+        }
+    }
+    (Ordering::Equal, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calc::Value;
+    use competition::{Subtask, Task, TaskStatus, Team};
+
+    fn competition() -> Competition {
+        let mut s = Series::new("sarja");
+        let mut t = Task::new("rasti");
+        t.subtasks.push(Subtask::new("a", "a", &["a"]));
+        t.subtasks.push(Subtask::new("b", "a*2", &["a"]));
+        s.tasks.push(t);
+        let mut t = Task::new("virhe");
+        t.subtasks.push(Subtask::new("c", "x", &[]));
+        s.tasks.push(t);
+        for (n, a) in [Some(2.0), Some(5.0), None, Some(2.0), Some(9.0)].iter().enumerate() {
+            let mut team = Team::new(n as u32 + 1, "");
+            if let Some(a) = *a {
+                team.set(s.find_key("rasti.a.a").unwrap(), Value::Num(a));
+                team.set(s.find_key("rasti.b.a").unwrap(), Value::Num(a));
+            }
+            team.participating = n != 4;
+            s.teams.push(team);
+        }
+        let mut c = Competition::new("kisa");
+        c.series.push(s);
+        c
+    }
+
+    #[test]
+    fn test_score() {
+        let c = competition();
+        let res = score(&c, &c.calculate());
+        assert_eq!(1, res.len());
+        let ranks: Vec<(u32, Option<usize>, f64)> = res[0]
+            .standings
+            .iter()
+            .map(|s| (c.series[0].teams[s.team].number, s.rank, s.total))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, Some(1), 15.0),
+                (1, Some(2), 6.0),
+                (4, Some(2), 6.0),
+                (3, Some(4), 0.0),
+                (5, None, 27.0),
+            ],
+            ranks
+        );
+        assert_eq!(vec![Some(15.0), None], res[0].standings[0].tasks);
+        assert_eq!(vec![None, None], res[0].standings[3].tasks);
+        assert_eq!(5, res[0].errors.len());
+        assert!(res[0].errors.iter().all(|p| p.task == 1));
+    }
+    #[test]
+    fn test_statuses() {
+        let mut c = competition();
+        c.series[0].teams[0].statuses.insert(0, TaskStatus::Disqualified);
+        c.series[0].teams[2].statuses.insert(0, TaskStatus::Penalty(1.0));
+        let res = c.results();
+        let ranks: Vec<(usize, Option<usize>, Option<f64>)> = res[0]
+            .standings
+            .iter()
+            .map(|s| (s.team + 1, s.rank, s.tasks[0]))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, Some(1), Some(15.0)),
+                (4, Some(2), Some(6.0)),
+                (1, Some(3), Some(0.0)),
+                (3, Some(4), Some(-1.0)),
+                (5, None, Some(27.0)),
+            ],
+            ranks
+        );
+    }
+    #[test]
+    fn test_tie_breaks() {
+        let mut s = Series::new("sarja");
+        for name in &["a", "b"] {
+            let mut t = Task::new(name);
+            t.subtasks.push(Subtask::new("o", "p", &["p"]));
+            s.tasks.push(t);
+        }
+        for (n, p) in [(5.0, 3.0), (3.0, 5.0), (4.0, 4.0), (1.0, 1.0)].iter().enumerate() {
+            let mut team = Team::new(n as u32 + 1, "");
+            team.set(s.find_key("a.o.p").unwrap(), Value::Num(p.0));
+            team.set(s.find_key("b.o.p").unwrap(), Value::Num(p.1));
+            s.teams.push(team);
+        }
+        let mut c = Competition::new("kisa");
+        c.series.push(s);
+        let mut ranks = |rules: Vec<TieBreak>| {
+            c.series[0].tie_breaks = rules;
+            let res = c.results();
+            res[0]
+                .standings
+                .iter()
+                .map(|s| (s.team + 1, s.rank, s.decided_by.clone()))
+                .take(3)
+                .collect::<Vec<_>>()
+        };
+        let a = || Some(TieBreak::Task("a".into()));
+        let b = || Some(TieBreak::Task("b".into()));
+        let best = || Some(TieBreak::BestPlacements);
+        let shared = || Some(TieBreak::Shared);
+        assert_eq!(vec![(1, Some(1), None), (2, Some(1), None), (3, Some(1), None)], ranks(vec![]));
+        assert_eq!(
+            vec![(2, Some(1), None), (3, Some(2), b()), (1, Some(3), b())],
+            ranks(vec![TieBreak::Task("b".into())])
+        );
+        assert_eq!(
+            vec![(1, Some(1), None), (2, Some(1), None), (3, Some(3), best())],
+            ranks(vec![TieBreak::BestPlacements])
+        );
+        assert_eq!(
+            vec![(1, Some(1), None), (2, Some(2), a()), (3, Some(3), best())],
+            ranks(vec![TieBreak::BestPlacements, TieBreak::Task("a".into())])
+        );
+        assert_eq!(
+            vec![(1, Some(1), None), (2, Some(1), shared()), (3, Some(1), shared())],
+            ranks(vec![TieBreak::Shared, TieBreak::Task("a".into())])
+        );
+    }
+}
@@ -0,0 +1,448 @@
+//! Reads the rows of an SQL dump, as written by `sqlite3 <db> .dump` or
+//! `mysqldump`. Only `CREATE TABLE` and `INSERT INTO` statements are read,
+//! everything else is skipped. Column names come from the `INSERT` if it
+//! lists them, otherwise from the `CREATE TABLE` of the table.
+//!
+//! Values are literals: numbers, strings, `NULL`, `TRUE` and `FALSE`.
+//! `char(...)` and `replace(...)`, which `sqlite3` writes for strings with
+//! line breaks, are evaluated. Strings of a dump of `mysqldump` have
+//! backslash escapes.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Number, Value as Json};
+
+use super::error::LoadError;
+
+/// Row of table `table` by column name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub table: String,
+    pub values: Map<String, Json>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    /// Keyword or unquoted name.
+    Word(String),
+    /// Name in `"..."`, `` `...` `` or `[...]`.
+    Name(String),
+    Str(String),
+    Num(String),
+    Punct(char),
+}
+
+/// Token and the byte offset it starts at.
+type Token = (Tok, usize);
+
+/// Error at byte `pos` of dump `s`, with its line.
+fn invalid(s: &str, pos: usize, m: &str) -> LoadError {
+    let line = s[..pos.min(s.len())].matches('\n').count() + 1;
+    LoadError::Invalid(format!("SQL dump, line {}: {}", line, m))
+}
+
+/// Whether `s` is a dump of `mysqldump`, whose strings have backslash
+/// escapes.
+fn is_mysql(s: &str) -> bool {
+    s.trim_start().starts_with("-- MySQL dump") || s.contains("INSERT INTO `")
+}
+
+fn tokens(s: &str) -> Result<Vec<Token>, LoadError> {
+    let backslash = is_mysql(s);
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    let mut res = Vec::new();
+    let mut i = 0;
+    let at = |i: usize| chars.get(i).map(|c| c.1);
+    while let Some(c) = at(i) {
+        let pos = chars[i].0;
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '-' if at(i + 1) == Some('-') => {
+                while at(i).map(|c| c != '\n').unwrap_or(false) {
+                    i += 1;
+                }
+            }
+            '/' if at(i + 1) == Some('*') => {
+                i += 2;
+                while at(i).is_some() && !(at(i) == Some('*') && at(i + 1) == Some('/')) {
+                    i += 1;
+                }
+                if at(i).is_none() {
+                    return Err(invalid(s, pos, "unterminated comment"));
+                }
+                i += 2;
+            }
+            '\'' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match at(i) {
+                        None => return Err(invalid(s, pos, "unterminated string")),
+                        Some('\'') if at(i + 1) == Some('\'') => {
+                            text.push('\'');
+                            i += 2;
+                        }
+                        Some('\'') => {
+                            i += 1;
+                            break;
+                        }
+                        Some('\\') if backslash => {
+                            text.push(match at(i + 1) {
+                                Some('n') => '\n',
+                                Some('r') => '\r',
+                                Some('t') => '\t',
+                                Some('0') => '\0',
+                                Some('Z') => '\u{1a}',
+                                Some(c) => c,
+                                None => return Err(invalid(s, pos, "unterminated string")),
+                            });
+                            i += 2;
+                        }
+                        Some(c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                res.push((Tok::Str(text), pos));
+            }
+            '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                let mut name = String::new();
+                i += 1;
+                loop {
+                    match at(i) {
+                        None => return Err(invalid(s, pos, "unterminated name")),
+                        Some(c) if c == close && at(i + 1) == Some(close) && close != ']' => {
+                            name.push(c);
+                            i += 2;
+                        }
+                        Some(c) if c == close => {
+                            i += 1;
+                            break;
+                        }
+                        Some(c) => {
+                            name.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                res.push((Tok::Name(name), pos));
+            }
+            c if c.is_ascii_digit() || (c == '.' && at(i + 1).map(|d| d.is_ascii_digit()).unwrap_or(false)) => {
+                let start = i;
+                while let Some(c) = at(i) {
+                    let exponent = (c == '+' || c == '-') && matches!(at(i - 1), Some('e') | Some('E'));
+                    if !(c.is_ascii_alphanumeric() || c == '.' || exponent) {
+                        break;
+                    }
+                    i += 1;
+                }
+                res.push((Tok::Num(chars[start..i].iter().map(|c| c.1).collect()), pos));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while at(i).map(|c| c.is_alphanumeric() || c == '_' || c == '$').unwrap_or(false) {
+                    i += 1;
+                }
+                res.push((Tok::Word(chars[start..i].iter().map(|c| c.1).collect()), pos));
+            }
+            c => {
+                res.push((Tok::Punct(c), pos));
+                i += 1;
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Whether `t` is keyword `k`.
+fn is_word(t: Option<&Token>, k: &str) -> bool {
+    match t {
+        Some(&(Tok::Word(ref w), _)) => w.eq_ignore_ascii_case(k),
+        _ => false,
+    }
+}
+
+fn is_punct(t: Option<&Token>, p: char) -> bool {
+    matches!(t, Some(&(Tok::Punct(c), _)) if c == p)
+}
+
+/// Statement being read, with the dump for errors.
+struct Statement<'a> {
+    s: &'a str,
+    toks: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Statement<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.toks.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let t = self.toks.get(self.pos);
+        self.pos += 1;
+        t
+    }
+
+    fn err(&self, m: &str) -> LoadError {
+        let pos = self.toks.get(self.pos).or_else(|| self.toks.last()).map(|t| t.1).unwrap_or(0);
+        invalid(self.s, pos, m)
+    }
+
+    fn skip_word(&mut self, k: &str) -> bool {
+        if is_word(self.peek(), k) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, p: char) -> Result<(), LoadError> {
+        if is_punct(self.peek(), p) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.err(&format!("expected {}", p)))
+        }
+    }
+
+    /// Name of a table or column. Of `schema.table` only the table is kept.
+    fn name(&mut self) -> Result<String, LoadError> {
+        let mut name = match self.next() {
+            Some(&(Tok::Word(ref n), _)) | Some(&(Tok::Name(ref n), _)) => n.clone(),
+            _ => return Err(self.err("expected a name")),
+        };
+        while is_punct(self.peek(), '.') {
+            self.pos += 1;
+            name = self.name()?;
+        }
+        Ok(name)
+    }
+
+    /// Columns of `CREATE TABLE`, after the name of the table.
+    fn columns(&mut self) -> Result<Vec<String>, LoadError> {
+        const CONSTRAINTS: &[&str] = &[
+            "PRIMARY", "KEY", "UNIQUE", "CONSTRAINT", "FOREIGN", "INDEX", "CHECK", "FULLTEXT", "SPATIAL",
+        ];
+        self.expect('(')?;
+        let mut columns = Vec::new();
+        loop {
+            if !CONSTRAINTS.iter().any(|k| is_word(self.peek(), k)) {
+                columns.push(self.name()?);
+            }
+            // Skips the rest of the definition.
+            let mut depth = 0;
+            loop {
+                match self.next() {
+                    None => return Err(self.err("unterminated CREATE TABLE")),
+                    Some(&(Tok::Punct('('), _)) => depth += 1,
+                    Some(&(Tok::Punct(')'), _)) if depth == 0 => return Ok(columns),
+                    Some(&(Tok::Punct(')'), _)) => depth -= 1,
+                    Some(&(Tok::Punct(','), _)) if depth == 0 => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Literal value, or a call of `char` or `replace`.
+    fn value(&mut self) -> Result<Json, LoadError> {
+        let negative = is_punct(self.peek(), '-');
+        if negative || is_punct(self.peek(), '+') {
+            self.pos += 1;
+        }
+        let t = self.next().ok_or_else(|| self.err("expected a value"))?;
+        match t.0 {
+            Tok::Num(ref n) => {
+                let n = if negative { format!("-{}", n) } else { n.clone() };
+                match n.parse::<i64>() {
+                    Ok(i) => Ok(Json::from(i)),
+                    Err(_) => n
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(Number::from_f64)
+                        .map(Json::Number)
+                        .ok_or_else(|| invalid(self.s, t.1, &format!("malformed number {}", n))),
+                }
+            }
+            _ if negative => Err(invalid(self.s, t.1, "expected a number")),
+            Tok::Str(ref s) => Ok(Json::String(s.clone())),
+            Tok::Word(ref w) if w.eq_ignore_ascii_case("null") => Ok(Json::Null),
+            Tok::Word(ref w) if w.eq_ignore_ascii_case("true") => Ok(Json::Bool(true)),
+            Tok::Word(ref w) if w.eq_ignore_ascii_case("false") => Ok(Json::Bool(false)),
+            Tok::Word(ref w) if is_punct(self.peek(), '(') => {
+                self.pos += 1;
+                let mut args = Vec::new();
+                if !is_punct(self.peek(), ')') {
+                    args.push(self.value()?);
+                    while is_punct(self.peek(), ',') {
+                        self.pos += 1;
+                        args.push(self.value()?);
+                    }
+                }
+                self.expect(')')?;
+                call(w, &args).ok_or_else(|| invalid(self.s, t.1, &format!("can not evaluate {}(...)", w)))
+            }
+            _ => Err(invalid(self.s, t.1, "expected a value")),
+        }
+    }
+
+    /// `(value, ...)`.
+    fn tuple(&mut self) -> Result<Vec<Json>, LoadError> {
+        self.expect('(')?;
+        let mut values = vec![self.value()?];
+        while is_punct(self.peek(), ',') {
+            self.pos += 1;
+            values.push(self.value()?);
+        }
+        self.expect(')')?;
+        Ok(values)
+    }
+}
+
+/// Functions `sqlite3` writes strings with line breaks with.
+fn call(f: &str, args: &[Json]) -> Option<Json> {
+    match (f.to_lowercase().as_str(), args) {
+        ("char", _) => args
+            .iter()
+            .map(|a| a.as_u64().and_then(|c| ::std::char::from_u32(c as u32)))
+            .collect::<Option<String>>()
+            .map(Json::String),
+        ("replace", [Json::String(s), Json::String(from), Json::String(to)]) if !from.is_empty() => {
+            Some(Json::String(s.replace(from.as_str(), to)))
+        }
+        _ => None,
+    }
+}
+
+/// Reads every row inserted by dump `s`, in order.
+pub fn rows(s: &str) -> Result<Vec<Row>, LoadError> {
+    let toks = tokens(s)?;
+    let mut tables: HashMap<String, Vec<String>> = HashMap::new();
+    let mut res = Vec::new();
+    let mut statements = 0;
+    for toks in toks.split(|t| t.0 == Tok::Punct(';')).filter(|t| !t.is_empty()) {
+        let mut st = Statement { s, toks, pos: 0 };
+        if st.skip_word("CREATE") {
+            st.skip_word("TEMPORARY");
+            if !st.skip_word("TABLE") {
+                continue;
+            }
+            if st.skip_word("IF") {
+                st.skip_word("NOT");
+                st.skip_word("EXISTS");
+            }
+            let table = st.name()?;
+            let columns = st.columns()?;
+            tables.insert(table, columns);
+            statements += 1;
+        } else if st.skip_word("INSERT") || st.skip_word("REPLACE") {
+            if st.skip_word("OR") {
+                st.next();
+            }
+            st.skip_word("IGNORE");
+            if !st.skip_word("INTO") {
+                return Err(st.err("expected INTO"));
+            }
+            let table = st.name()?;
+            let columns = if is_punct(st.peek(), '(') {
+                st.pos += 1;
+                let mut columns = vec![st.name()?];
+                while is_punct(st.peek(), ',') {
+                    st.pos += 1;
+                    columns.push(st.name()?);
+                }
+                st.expect(')')?;
+                columns
+            } else {
+                tables
+                    .get(&table)
+                    .cloned()
+                    .ok_or_else(|| st.err(&format!("no CREATE TABLE for {}", table)))?
+            };
+            if !st.skip_word("VALUES") {
+                return Err(st.err("expected VALUES"));
+            }
+            loop {
+                let values = st.tuple()?;
+                if values.len() != columns.len() {
+                    return Err(st.err(&format!("{} has {} columns but got {} values", table, columns.len(), values.len())));
+                }
+                res.push(Row {
+                    table: table.clone(),
+                    values: columns.iter().cloned().zip(values).collect(),
+                });
+                if !is_punct(st.peek(), ',') {
+                    break;
+                }
+                st.pos += 1;
+            }
+            if st.peek().is_some() {
+                return Err(st.err("expected ;"));
+            }
+            statements += 1;
+        }
+    }
+    if statements == 0 {
+        return Err(LoadError::Invalid("no CREATE TABLE or INSERT in SQL dump".into()));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(table: &str, values: &[(&str, Json)]) -> Row {
+        Row {
+            table: table.into(),
+            values: values.iter().map(|&(k, ref v)| (k.to_string(), v.clone())).collect(),
+        }
+    }
+
+    #[test]
+    fn test_sqlite() {
+        let dump = "PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\n\
+                    CREATE TABLE \"t\" (\"id\" integer NOT NULL PRIMARY KEY, \"nimi\" varchar(255) NOT NULL, \
+                    \"x\" decimal(5, 2) NULL, UNIQUE (\"nimi\"));\n\
+                    INSERT INTO \"t\" VALUES(1,'O''Neil; a\\b',-1.5);\n\
+                    INSERT INTO t VALUES(2,replace('a\\nb','\\n',char(10)),NULL);\n\
+                    INSERT INTO t (nimi, id) VALUES ('c', 3);\nCOMMIT;\n";
+        assert_eq!(
+            vec![
+                row("t", &[("id", Json::from(1)), ("nimi", Json::from("O'Neil; a\\b")), ("x", Json::from(-1.5))]),
+                row("t", &[("id", Json::from(2)), ("nimi", Json::from("a\nb")), ("x", Json::Null)]),
+                row("t", &[("nimi", Json::from("c")), ("id", Json::from(3))]),
+            ],
+            rows(dump).unwrap()
+        );
+    }
+    #[test]
+    fn test_mysql() {
+        let dump = "-- MySQL dump 10.13\n/*!40101 SET NAMES utf8 */;\n\
+                    CREATE TABLE `t` (\n  `id` int(11) NOT NULL AUTO_INCREMENT,\n  `a` varchar(9),\n  \
+                    PRIMARY KEY (`id`),\n  KEY `t_a` (`a`)\n) ENGINE=InnoDB;\n\
+                    INSERT INTO `t` VALUES (1,'it\\'s'),(2,'a\\nb');\n";
+        assert_eq!(
+            vec![
+                row("t", &[("id", Json::from(1)), ("a", Json::from("it's"))]),
+                row("t", &[("id", Json::from(2)), ("a", Json::from("a\nb"))]),
+            ],
+            rows(dump).unwrap()
+        );
+    }
+    #[test]
+    fn test_invalid() {
+        let err = |s: &str| rows(s).unwrap_err().to_string();
+        assert_eq!("invalid competition: SQL dump, line 2: no CREATE TABLE for t", err("\nINSERT INTO t VALUES (1);"));
+        assert_eq!(
+            "invalid competition: SQL dump, line 1: t has 1 columns but got 2 values",
+            err("CREATE TABLE t (a);INSERT INTO t VALUES (1, 2);")
+        );
+        assert_eq!("invalid competition: SQL dump, line 1: unterminated string", err("INSERT INTO t VALUES ('a"));
+        assert_eq!("invalid competition: no CREATE TABLE or INSERT in SQL dump", err("{}"));
+    }
+}
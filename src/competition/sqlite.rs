@@ -0,0 +1,726 @@
+//! Competitions stored in an SQLite file, the `sqlite` db type of kwp. The
+//! database holds the same model as `Competition` and the points calculated
+//! for it, so they survive restarts of Kilac.
+//!
+//! Formulas are evaluated against the database by `SqliteCtx`, which
+//! resolves every getter with a query instead of loading the competition
+//! into memory. Names and team numbers are unique within their parent and
+//! thus indexed. Tie-break rules of a series are stored as JSON, like in
+//! `json`.
+//!
+//! The schema is created and upgraded with `MIGRATIONS` when the database is
+//! opened. `PRAGMA user_version` tells how many of them have been run.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, OptionalExtension};
+use serde_json;
+
+use calc::ctx::KilaCtx;
+use calc::error::KilaError;
+use calc::getter::{Getter, Path as GetterPath, Scope};
+use calc::span::Span;
+use calc::{calculate_err, Value};
+
+use super::error::LoadError;
+use super::{Competition, InputDef, InputId, InputKind, Points, Series, Subtask, Task, TaskStatus, Team};
+
+/// Schema changes in the order they are run. Never change one that has
+/// been released, add a new one instead.
+pub const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE competition (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE series (
+        id INTEGER PRIMARY KEY,
+        competition INTEGER NOT NULL REFERENCES competition(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        UNIQUE (competition, name)
+    );
+    CREATE TABLE task (
+        id INTEGER PRIMARY KEY,
+        series INTEGER NOT NULL REFERENCES series(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        UNIQUE (series, name)
+    );
+    CREATE TABLE subtask (
+        id INTEGER PRIMARY KEY,
+        task INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        formula TEXT NOT NULL,
+        UNIQUE (task, name)
+    );
+    CREATE TABLE input_def (
+        id INTEGER PRIMARY KEY,
+        subtask INTEGER NOT NULL REFERENCES subtask(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        UNIQUE (subtask, name)
+    );
+    CREATE TABLE team (
+        id INTEGER PRIMARY KEY,
+        series INTEGER NOT NULL REFERENCES series(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        number INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        participating INTEGER NOT NULL,
+        UNIQUE (series, number)
+    );
+    CREATE TABLE input (
+        team INTEGER NOT NULL REFERENCES team(id) ON DELETE CASCADE,
+        input_def INTEGER NOT NULL REFERENCES input_def(id) ON DELETE CASCADE,
+        value REAL NOT NULL,
+        PRIMARY KEY (team, input_def)
+    );",
+    "CREATE TABLE points (
+        subtask INTEGER NOT NULL REFERENCES subtask(id) ON DELETE CASCADE,
+        team INTEGER NOT NULL REFERENCES team(id) ON DELETE CASCADE,
+        points REAL,
+        error TEXT,
+        PRIMARY KEY (subtask, team)
+    );
+    CREATE INDEX team_position ON team (series, position);",
+    "ALTER TABLE series ADD COLUMN tie_breaks TEXT NOT NULL DEFAULT '[]';",
+    "CREATE TABLE task_status (
+        team INTEGER NOT NULL REFERENCES team(id) ON DELETE CASCADE,
+        task INTEGER NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+        disqualified INTEGER NOT NULL,
+        penalty REAL NOT NULL,
+        PRIMARY KEY (team, task)
+    );",
+];
+
+/// Connection to a database of competitions.
+#[derive(Debug)]
+pub struct Db {
+    conn: Connection,
+}
+
+impl Db {
+    /// Opens the database in file `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Db, LoadError> {
+        Db::init(Connection::open(path)?)
+    }
+
+    /// Opens a new database in memory.
+    pub fn open_in_memory() -> Result<Db, LoadError> {
+        Db::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Db, LoadError> {
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        let mut db = Db { conn };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    /// Number of migrations run on the database.
+    pub fn version(&self) -> Result<usize, LoadError> {
+        let v: i64 = self.conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+        Ok(v as usize)
+    }
+
+    fn migrate(&mut self) -> Result<(), LoadError> {
+        let version = self.version()?;
+        if version > MIGRATIONS.len() {
+            return Err(LoadError::Invalid(format!(
+                "database is of version {}, newer than {} known to Kilac",
+                version,
+                MIGRATIONS.len()
+            )));
+        }
+        for (i, m) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(m)?;
+            tx.pragma_update(None, "user_version", i as i64 + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Names of the competitions in the database.
+    pub fn competitions(&self) -> Result<Vec<String>, LoadError> {
+        let mut stmt = self.conn.prepare("SELECT name FROM competition ORDER BY name")?;
+        let names = stmt.query_map([], |r| r.get(0))?;
+        Ok(names.collect::<Result<_, _>>()?)
+    }
+
+    /// Stores a competition, replacing the one with the same name and its
+    /// points.
+    pub fn save(&mut self, c: &Competition) -> Result<(), LoadError> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM competition WHERE name = ?1", [&c.name])?;
+        tx.execute("INSERT INTO competition (name) VALUES (?1)", [&c.name])?;
+        let cid = tx.last_insert_rowid();
+        for (si, s) in c.series.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO series (competition, position, name, tie_breaks) VALUES (?1, ?2, ?3, ?4)",
+                params(&[&cid, &(si as i64), &s.name, &serde_json::to_string(&s.tie_breaks)?]),
+            )?;
+            let sid = tx.last_insert_rowid();
+            let mut defs = HashMap::new();
+            let mut tasks = Vec::new();
+            for (ti, t) in s.tasks.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO task (series, position, name) VALUES (?1, ?2, ?3)",
+                    params(&[&sid, &(ti as i64), &t.name]),
+                )?;
+                let tid = tx.last_insert_rowid();
+                tasks.push(tid);
+                for (oi, o) in t.subtasks.iter().enumerate() {
+                    tx.execute(
+                        "INSERT INTO subtask (task, position, name, formula) VALUES (?1, ?2, ?3, ?4)",
+                        params(&[&tid, &(oi as i64), &o.name, &o.formula]),
+                    )?;
+                    let oid = tx.last_insert_rowid();
+                    for (ii, i) in o.inputs.iter().enumerate() {
+                        tx.execute(
+                            "INSERT INTO input_def (subtask, position, name, kind) VALUES (?1, ?2, ?3, ?4)",
+                            params(&[&oid, &(ii as i64), &i.name, &kind_name(i.kind)]),
+                        )?;
+                        let id = InputId {
+                            task: ti,
+                            subtask: oi,
+                            input: ii,
+                        };
+                        defs.insert(id, tx.last_insert_rowid());
+                    }
+                }
+            }
+            for (pos, team) in s.teams.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO team (series, position, number, name, participating)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params(&[&sid, &(pos as i64), &team.number, &team.name, &team.participating]),
+                )?;
+                let team_id = tx.last_insert_rowid();
+                for (id, v) in &team.inputs {
+                    let def = defs.get(id).ok_or_else(|| {
+                        LoadError::Invalid(format!("team {} has an undefined input", team.number))
+                    })?;
+                    if let Some(v) = v.as_num() {
+                        tx.execute(
+                            "INSERT INTO input (team, input_def, value) VALUES (?1, ?2, ?3)",
+                            params(&[&team_id, def, &v]),
+                        )?;
+                    }
+                }
+                for (task, status) in &team.statuses {
+                    let task = tasks.get(*task).ok_or_else(|| {
+                        LoadError::Invalid(format!("team {} has a status in an undefined task", team.number))
+                    })?;
+                    let (disqualified, penalty) = match *status {
+                        TaskStatus::Disqualified => (true, 0.0),
+                        TaskStatus::Penalty(p) => (false, p),
+                    };
+                    tx.execute(
+                        "INSERT INTO task_status (team, task, disqualified, penalty) VALUES (?1, ?2, ?3, ?4)",
+                        params(&[&team_id, task, &disqualified, &penalty]),
+                    )?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Reads the competition called `name`.
+    pub fn load(&self, name: &str) -> Result<Competition, LoadError> {
+        let cid = self.competition_id(name)?;
+        let mut c = Competition::new(name);
+        let mut series = self.conn.prepare_cached(
+            "SELECT id, name, tie_breaks FROM series WHERE competition = ?1 ORDER BY position",
+        )?;
+        let rows = series.query_map([cid], |r| {
+            Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?))
+        })?;
+        for row in rows {
+            let (sid, name, tie_breaks) = row?;
+            let mut s = self.load_series(sid, &name)?;
+            s.tie_breaks = serde_json::from_str(&tie_breaks)?;
+            c.series.push(s);
+        }
+        Ok(c)
+    }
+
+    fn load_series(&self, sid: i64, name: &str) -> Result<Series, LoadError> {
+        let mut s = Series::new(name);
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT t.name, o.name, o.formula, i.name, i.kind
+             FROM task t
+             LEFT JOIN subtask o ON o.task = t.id
+             LEFT JOIN input_def i ON i.subtask = o.id
+             WHERE t.series = ?1
+             ORDER BY t.position, o.position, i.position",
+        )?;
+        let mut rows = stmt.query([sid])?;
+        while let Some(r) = rows.next()? {
+            let task: String = r.get(0)?;
+            if s.tasks.last().map(|t| t.name != task).unwrap_or(true) {
+                s.tasks.push(Task::new(&task));
+            }
+            let t = s.tasks.last_mut().unwrap();
+            let subtask: Option<String> = r.get(1)?;
+            if let Some(subtask) = subtask {
+                if t.subtasks.last().map(|o| o.name != subtask).unwrap_or(true) {
+                    t.subtasks.push(Subtask::new(&subtask, &r.get::<_, String>(2)?, &[]));
+                }
+                let input: Option<String> = r.get(3)?;
+                if let Some(input) = input {
+                    let kind = parse_kind(&r.get::<_, String>(4)?)?;
+                    let o = t.subtasks.last_mut().unwrap();
+                    o.inputs.push(InputDef { name: input, kind });
+                }
+            }
+        }
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, number, name, participating FROM team WHERE series = ?1 ORDER BY position",
+        )?;
+        let mut rows = stmt.query([sid])?;
+        let mut ids = HashMap::new();
+        while let Some(r) = rows.next()? {
+            let mut team = Team::new(r.get(1)?, &r.get::<_, String>(2)?);
+            team.participating = r.get(3)?;
+            ids.insert(r.get::<_, i64>(0)?, s.teams.len());
+            s.teams.push(team);
+        }
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT i.team, t.position, o.position, d.position, d.kind, i.value
+             FROM input i
+             JOIN input_def d ON d.id = i.input_def
+             JOIN subtask o ON o.id = d.subtask
+             JOIN task t ON t.id = o.task
+             WHERE t.series = ?1",
+        )?;
+        let mut rows = stmt.query([sid])?;
+        while let Some(r) = rows.next()? {
+            let id = InputId {
+                task: r.get::<_, i64>(1)? as usize,
+                subtask: r.get::<_, i64>(2)? as usize,
+                input: r.get::<_, i64>(3)? as usize,
+            };
+            let v = value(parse_kind(&r.get::<_, String>(4)?)?, r.get(5)?);
+            s.teams[ids[&r.get::<_, i64>(0)?]].set(id, v);
+        }
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT st.team, t.position, st.disqualified, st.penalty
+             FROM task_status st
+             JOIN task t ON t.id = st.task
+             WHERE t.series = ?1",
+        )?;
+        let mut rows = stmt.query([sid])?;
+        while let Some(r) = rows.next()? {
+            let status = if r.get(2)? {
+                TaskStatus::Disqualified
+            } else {
+                TaskStatus::Penalty(r.get(3)?)
+            };
+            let task = r.get::<_, i64>(1)? as usize;
+            s.teams[ids[&r.get::<_, i64>(0)?]].statuses.insert(task, status);
+        }
+        Ok(s)
+    }
+
+    fn competition_id(&self, name: &str) -> Result<i64, LoadError> {
+        self.conn
+            .query_row("SELECT id FROM competition WHERE name = ?1", [name], |r| r.get(0))
+            .optional()?
+            .ok_or_else(|| LoadError::Invalid(format!("no competition {}", name)))
+    }
+
+    /// Calculates the formula of every subtask of the competition called
+    /// `name` for every team of its series, like `Competition::calculate`,
+    /// and stores the points. Teams disqualified from a task get zero
+    /// points from its subtasks.
+    pub fn calculate(&mut self, name: &str) -> Result<Vec<Points>, LoadError> {
+        self.calculate_in(name, None, None)
+    }
+
+    /// Like `calculate`, but only the subtasks of series `series` and of its
+    /// task `task` are calculated, when given. Points of other subtasks are
+    /// left as they are.
+    pub fn calculate_in(
+        &mut self,
+        name: &str,
+        series: Option<usize>,
+        task: Option<usize>,
+    ) -> Result<Vec<Points>, LoadError> {
+        let cid = self.competition_id(name)?;
+        let (series, task) = (series.map(|s| s as i64), task.map(|t| t as i64));
+        let mut res = Vec::new();
+        let mut keys = Vec::new();
+        {
+            let mut stmt = self.conn.prepare(
+                "SELECT o.id, s.position, t.position, o.position, o.formula, m.id, m.position,
+                        COALESCE(st.disqualified, 0)
+                 FROM series s
+                 JOIN task t ON t.series = s.id
+                 JOIN subtask o ON o.task = t.id
+                 JOIN team m ON m.series = s.id
+                 LEFT JOIN task_status st ON st.team = m.id AND st.task = t.id
+                 WHERE s.competition = ?1
+                   AND (?2 IS NULL OR s.position = ?2)
+                   AND (?3 IS NULL OR t.position = ?3)
+                 ORDER BY s.position, t.position, o.position, m.position",
+            )?;
+            let mut rows = stmt.query(params(&[&cid, &series, &task]))?;
+            while let Some(r) = rows.next()? {
+                let (subtask, team): (i64, i64) = (r.get(0)?, r.get(5)?);
+                let points = if r.get(7)? {
+                    Ok(Some(0.0))
+                } else {
+                    calculate_err(r.get(4)?, SqliteCtx::new(self, subtask, team)?)
+                };
+                res.push(Points {
+                    series: r.get::<_, i64>(1)? as usize,
+                    task: r.get::<_, i64>(2)? as usize,
+                    subtask: r.get::<_, i64>(3)? as usize,
+                    team: r.get::<_, i64>(6)? as usize,
+                    points,
+                });
+                keys.push((subtask, team));
+            }
+        }
+        let tx = self.conn.transaction()?;
+        for (&(subtask, team), p) in keys.iter().zip(&res) {
+            let (points, error) = match p.points {
+                Ok(v) => (v, None),
+                Err(ref e) => (None, Some(e.to_string())),
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO points (subtask, team, points, error) VALUES (?1, ?2, ?3, ?4)",
+                params(&[&subtask, &team, &points, &error]),
+            )?;
+        }
+        tx.commit()?;
+        Ok(res)
+    }
+
+    /// Points stored by the last `calculate` of the competition called
+    /// `name`. Errors are stored as text, so they come back as
+    /// `KilaError::Context`.
+    pub fn points(&self, name: &str) -> Result<Vec<Points>, LoadError> {
+        let cid = self.competition_id(name)?;
+        let mut stmt = self.conn.prepare(
+            "SELECT s.position, t.position, o.position, m.position, p.points, p.error
+             FROM points p
+             JOIN subtask o ON o.id = p.subtask
+             JOIN task t ON t.id = o.task
+             JOIN series s ON s.id = t.series
+             JOIN team m ON m.id = p.team
+             WHERE s.competition = ?1
+             ORDER BY s.position, t.position, o.position, m.position",
+        )?;
+        let rows = stmt.query_map([cid], |r| {
+            let error: Option<String> = r.get(5)?;
+            Ok(Points {
+                series: r.get::<_, i64>(0)? as usize,
+                task: r.get::<_, i64>(1)? as usize,
+                subtask: r.get::<_, i64>(2)? as usize,
+                team: r.get::<_, i64>(3)? as usize,
+                points: match error {
+                    Some(e) => Err(KilaError::Context(e, Span::default())),
+                    None => Ok(r.get(4)?),
+                },
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+fn params<'a>(p: &'a [&'a dyn ToSql]) -> &'a [&'a dyn ToSql] {
+    p
+}
+
+fn kind_name(kind: InputKind) -> &'static str {
+    match kind {
+        InputKind::Num => "num",
+        InputKind::Time => "time",
+    }
+}
+
+fn parse_kind(s: &str) -> Result<InputKind, LoadError> {
+    match s {
+        "num" => Ok(InputKind::Num),
+        "time" => Ok(InputKind::Time),
+        _ => Err(LoadError::Invalid(format!("unknown input kind {}", s))),
+    }
+}
+
+fn value(kind: InputKind, v: Option<f64>) -> Value {
+    match (kind, v) {
+        (_, None) => Value::Empty,
+        (InputKind::Num, Some(v)) => Value::Num(v),
+        (InputKind::Time, Some(v)) => Value::Time(v),
+    }
+}
+
+/// Team id, participation, disqualification and value of an input.
+type TeamValue = (i64, bool, bool, Option<f64>);
+
+/// Evaluates formulas of a subtask for a team against the database, like
+/// `ctx::CompetitionCtx`. Subtasks and teams are given by their row ids.
+#[derive(Debug, Clone, Copy)]
+pub struct SqliteCtx<'a> {
+    db: &'a Db,
+    competition: i64,
+    series: i64,
+    task: i64,
+    subtask: i64,
+    team: i64,
+}
+
+impl<'a> SqliteCtx<'a> {
+    pub fn new(db: &'a Db, subtask: i64, team: i64) -> Result<SqliteCtx<'a>, LoadError> {
+        let (competition, series, task) = db.conn.query_row(
+            "SELECT s.competition, s.id, t.id
+             FROM subtask o JOIN task t ON t.id = o.task JOIN series s ON s.id = t.series
+             WHERE o.id = ?1",
+            [subtask],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )?;
+        Ok(SqliteCtx {
+            db,
+            competition,
+            series,
+            task,
+            subtask,
+            team,
+        })
+    }
+
+    /// Id of the row called `name` in `table` under `parent`.
+    fn find(&self, table: &str, parent: &str, id: i64, name: &str) -> rusqlite::Result<Option<i64>> {
+        let sql = format!("SELECT id FROM {} WHERE {} = ?1 AND name = ?2", table, parent);
+        let mut stmt = self.db.conn.prepare_cached(&sql)?;
+        stmt.query_row(params(&[&id, &name]), |r| r.get(0)).optional()
+    }
+
+    /// Value of an input.
+    fn input(&self, g: &Getter, p: &GetterPath) -> rusqlite::Result<Result<Value, KilaError>> {
+        let unknown = || Ok(Err(KilaError::UnknownIdentifier(g.to_string(), Span::default())));
+        let mut ids = (self.series, self.task, self.subtask);
+        if let Some(ref s) = p.series {
+            match self.find("series", "competition", self.competition, s)? {
+                Some(id) => ids.0 = id,
+                None => return unknown(),
+            }
+        }
+        if let Some(ref t) = p.task {
+            match self.find("task", "series", ids.0, t)? {
+                Some(id) => ids.1 = id,
+                None => return unknown(),
+            }
+        }
+        if let Some(ref o) = p.subtask {
+            match self.find("subtask", "task", ids.1, o)? {
+                Some(id) => ids.2 = id,
+                None => return unknown(),
+            }
+        }
+        let (def, kind): (i64, String) = match self.db
+            .conn
+            .prepare_cached("SELECT id, kind FROM input_def WHERE subtask = ?1 AND name = ?2")?
+            .query_row(params(&[&ids.2, &p.input]), |r| Ok((r.get(0)?, r.get(1)?)))
+            .optional()?
+        {
+            Some(d) => d,
+            None => return unknown(),
+        };
+        let kind = if kind == "time" { InputKind::Time } else { InputKind::Num };
+        let select = "SELECT m.id, m.participating, COALESCE(st.disqualified, 0), i.value
+                      FROM team m
+                      LEFT JOIN input i ON i.team = m.id AND i.input_def = ?1
+                      LEFT JOIN task_status st ON st.team = m.id AND st.task = ?2";
+        let rows = match p.scope {
+            Scope::Own => {
+                let sql = format!("{} WHERE m.id = ?3", select);
+                self.values(&sql, &[&def, &ids.1, &self.team])?
+            }
+            Scope::Team(n) => {
+                let sql = format!("{} WHERE m.series = ?3 AND m.number = ?4", select);
+                self.values(&sql, &[&def, &ids.1, &ids.0, &n])?
+            }
+            Scope::Series => {
+                let sql = format!("{} WHERE m.series = ?3 ORDER BY m.position", select);
+                self.values(&sql, &[&def, &ids.1, &ids.0])?
+            }
+        };
+        // Inputs of teams disqualified from the task count only for
+        // themselves.
+        let vals: Vec<Value> = rows.into_iter()
+            .map(|(id, part, disqualified, v)| {
+                if (p.participating && !part) || (disqualified && id != self.team) {
+                    Value::Empty
+                } else {
+                    value(kind, v)
+                }
+            })
+            .collect();
+        Ok(Ok(match p.scope {
+            Scope::Series => Value::List(vals),
+            Scope::Own => vals.into_iter().next().unwrap_or(Value::Empty),
+            Scope::Team(n) => match vals.into_iter().next() {
+                Some(v) => v,
+                None => {
+                    return Ok(Err(KilaError::Context(
+                        format!("no team {} in series", n),
+                        Span::default(),
+                    )))
+                }
+            },
+        }))
+    }
+
+    /// Values of an input for the teams selected by `sql`.
+    fn values(&self, sql: &str, p: &[&dyn ToSql]) -> rusqlite::Result<Vec<TeamValue>> {
+        let mut stmt = self.db.conn.prepare_cached(sql)?;
+        let rows = stmt.query_map(p, |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))?;
+        rows.collect()
+    }
+
+    fn resolve(&self, g: &Getter) -> rusqlite::Result<Result<Value, KilaError>> {
+        Ok(Ok(match *g {
+            Getter::Number => {
+                let n: f64 = self.db
+                    .conn
+                    .query_row("SELECT number FROM team WHERE id = ?1", [self.team], |r| r.get(0))?;
+                Value::Num(n)
+            }
+            Getter::Participation => {
+                let sql = "SELECT participating FROM team WHERE series = ?1 ORDER BY position";
+                let mut stmt = self.db.conn.prepare_cached(sql)?;
+                let rows = stmt.query_map([self.series], |r| r.get::<_, bool>(0))?;
+                let muk: rusqlite::Result<Vec<Value>> = rows
+                    .map(|p| p.map(|p| if p { Value::Num(1.0) } else { Value::Empty }))
+                    .collect();
+                Value::List(muk?)
+            }
+            Getter::Disqualified | Getter::Penalty => {
+                let status: Option<(bool, f64)> = self.db
+                    .conn
+                    .prepare_cached("SELECT disqualified, penalty FROM task_status WHERE team = ?1 AND task = ?2")?
+                    .query_row([self.team, self.task], |r| Ok((r.get(0)?, r.get(1)?)))
+                    .optional()?;
+                let (disqualified, penalty) = status.unwrap_or((false, 0.0));
+                match *g {
+                    Getter::Disqualified => Value::Bool(disqualified),
+                    _ => Value::Num(penalty),
+                }
+            }
+            Getter::Input(ref p) => return self.input(g, p),
+        }))
+    }
+}
+
+impl<'a> KilaCtx for SqliteCtx<'a> {
+    fn get(&self, g: &Getter) -> Result<Value, KilaError> {
+        self.resolve(g)
+            .unwrap_or_else(|e| Err(KilaError::Context(e.to_string(), Span::default())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use competition::json;
+
+    const EXAMPLE: &str = include_str!("../../doc/competition.json");
+
+    #[test]
+    fn test_migrations() {
+        let db = Db::open_in_memory().unwrap();
+        assert_eq!(MIGRATIONS.len(), db.version().unwrap());
+        db.conn.pragma_update(None, "user_version", 99).unwrap();
+        let mut db = db;
+        assert!(db.migrate().is_err());
+    }
+    #[test]
+    fn test_save_load() {
+        let c = json::from_str(EXAMPLE).unwrap();
+        let mut db = Db::open_in_memory().unwrap();
+        db.save(&c).unwrap();
+        db.save(&c).unwrap();
+        assert_eq!(vec![c.name.clone()], db.competitions().unwrap());
+        assert_eq!(c, db.load(&c.name).unwrap());
+        assert!(db.load("x").is_err());
+    }
+    #[test]
+    fn test_calculate() {
+        let c = json::from_str(EXAMPLE).unwrap();
+        let mut db = Db::open_in_memory().unwrap();
+        db.save(&c).unwrap();
+        let points = db.calculate(&c.name).unwrap();
+        assert_eq!(c.calculate(), points);
+        assert_eq!(points, db.points(&c.name).unwrap());
+    }
+    #[test]
+    fn test_calculate_in() {
+        let c = json::from_str(EXAMPLE).unwrap();
+        let mut db = Db::open_in_memory().unwrap();
+        db.save(&c).unwrap();
+        let all = c.calculate();
+        let task: Vec<Points> = all.iter().filter(|p| p.series == 0 && p.task == 0).cloned().collect();
+        assert_eq!(task, db.calculate_in(&c.name, Some(0), Some(0)).unwrap());
+        // Only the points of the task are stored.
+        assert_eq!(task, db.points(&c.name).unwrap());
+        let other: Vec<Points> = all.iter().filter(|p| p.task == 1).cloned().collect();
+        assert_eq!(other, db.calculate_in(&c.name, Some(0), Some(1)).unwrap());
+        assert_eq!(all, db.points(&c.name).unwrap());
+        assert_eq!(all, db.calculate_in(&c.name, Some(0), None).unwrap());
+    }
+    #[test]
+    fn test_getters() {
+        let mut s = Series::new("sarja");
+        let mut t = Task::new("start");
+        t.subtasks.push(Subtask::new("a", "a", &["a"]));
+        t.subtasks.push(Subtask::new("c", "a", &["a"]));
+        s.tasks.push(t);
+        let a = s.find_key("start.a.a").unwrap();
+        for (n, v) in [12.0, 8.0, 20.0].iter().enumerate() {
+            let mut team = Team::new(n as u32 + 1, "");
+            team.set(a, Value::Num(*v));
+            team.participating = n != 2;
+            if n == 0 {
+                team.statuses.insert(0, TaskStatus::Disqualified);
+            }
+            s.teams.push(team);
+        }
+        let mut c = Competition::new("kisa");
+        c.series.push(s);
+        let mut db = Db::open_in_memory().unwrap();
+        db.save(&c).unwrap();
+        let ids: (i64, i64) = db.conn
+            .query_row(
+                "SELECT o.id, m.id FROM subtask o, team m WHERE o.name = 'c' AND m.number = 2",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        let ctx = SqliteCtx::new(&db, ids.0, ids.1).unwrap();
+        let get = |g: &str| ctx.get(&Getter::parse(g).unwrap());
+        let n = |v: f64| Value::Num(v);
+        assert_eq!(Ok(n(2.0)), get("vartio"));
+        assert_eq!(Ok(Value::Empty), get("a"));
+        assert_eq!(Ok(n(8.0)), get(".a.a.vartio"));
+        assert_eq!(Ok(n(20.0)), get(".a.a.3"));
+        assert_eq!(Ok(Value::List(vec![Value::Empty, n(8.0), n(20.0)])), get("..start.a.a"));
+        assert_eq!(Ok(Value::List(vec![Value::Empty, n(8.0), Value::Empty])), get("muk.sarja.start.a.a"));
+        assert_eq!(Ok(Value::Bool(false)), get("vartio.hylatty"));
+        assert_eq!(Ok(n(0.0)), get("vartio.sakko"));
+        assert_eq!(Ok(Value::List(vec![n(1.0), n(1.0), Value::Empty])), get("muk"));
+        assert_eq!(Err(104), get(".b.a").map_err(|e| e.code()));
+        assert_eq!(Err(300), get(".a.a.9").map_err(|e| e.code()));
+        let points = calculate_err(".a.a.vartio/max(.a.a*muk)*10".into(), ctx);
+        assert_eq!(Ok(Some(10.0)), points);
+        assert_eq!(c, db.load("kisa").unwrap());
+        assert_eq!(c.calculate(), db.calculate("kisa").unwrap());
+    }
+}
@@ -51,7 +51,14 @@
 //! .b.a.vartio (input a for team from subtask b)
 //! ..start.c.a.vartio (input a for team from subtask c from task start)
 //! ```
+//! These and more formulas are checked against expected results in the
+//! corpus of tests/corpus, run by `cargo test --test kipa`.
+//!
 //! In addition to these Kilac supports other operators too.
+//!
+//! Teams that did not return an input ("tyhjä") are left out of the
+//! aggregates `min`, `max`, `sum`, `med` and `kesk`, as they are in Kipa.
+//! If no team returned the input, the aggregate has no value.
 
 /// Function aikavali calculates the time difference between two inputs
 /// in seconds across two dates.
@@ -98,50 +105,76 @@ pub fn kmod(a: f64, b: f64) -> f64 {
 pub fn pow(a: f64, b: f64) -> f64 {
     a.powf(b)
 }
-#[allow(unused_variables)]
-/// Raw interpolation.
+/// Linear interpolation through points (x1, y1) and (x2, y2). As in Kipa
+/// the result is clamped to the range: at or beyond x1 it is y1 and at or
+/// beyond x2 it is y2, whichever way round x1 and x2 are. If x1 equals x2
+/// there is no range and the result is y1. Kipa formulas usually leave y2
+/// out, in which case it is 0 and the result is the old `y1*(x-x2)/(x1-x2)`.
 pub fn interpoloi(x: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
-    y1 * (x - x2) / (x1 - x2)
+    if x1 == x2 {
+        return y1;
+    }
+    let t = (x - x1) / (x2 - x1);
+    if t <= 0.0 {
+        y1
+    } else if t >= 1.0 {
+        y2
+    } else {
+        y1 * (x - x2) / (x1 - x2) + y2 * t
+    }
 }
-/// Returns minimum value of f64 vector.
-pub fn min(x: Vec<f64>) -> f64 {
-    x.iter().cloned().fold(0. / 0., f64::min)
+/// Returns minimum value of a vector. Like the rest of the aggregates, skips
+/// missing inputs and returns `None` if there are no inputs left, as Kipa
+/// does for teams that did not return an input.
+pub fn min(x: Vec<Option<f64>>) -> Option<f64> {
+    given(x).into_iter().fold(None, |m, v| Some(m.map_or(v, |m: f64| m.min(v))))
 }
-/// Returns maximum value of f64 vector.
-pub fn max(x: Vec<f64>) -> f64 {
-    x.iter().cloned().fold(0. / 0., f64::max)
+/// Returns maximum value of a vector.
+pub fn max(x: Vec<Option<f64>>) -> Option<f64> {
+    given(x).into_iter().fold(None, |m, v| Some(m.map_or(v, |m: f64| m.max(v))))
 }
 /// Takes sum of all values in a vector.
-pub fn sum(x: Vec<f64>) -> f64 {
-    x.iter().sum()
+pub fn sum(x: Vec<Option<f64>>) -> Option<f64> {
+    let a = given(x);
+    if a.is_empty() {
+        return None;
+    }
+    Some(a.iter().sum())
 }
-/// Takes mean of vector
-pub fn mean(x: Vec<f64>) -> f64 {
-    let a = x.len();
-    let b: f64 = x.iter().sum();
-    b / (a as f64)
+/// Takes mean of vector. Missing inputs are not counted in the divisor.
+pub fn mean(x: Vec<Option<f64>>) -> Option<f64> {
+    let a = given(x);
+    let ln = a.len();
+    sum(a.into_iter().map(Some).collect()).map(|b| b / (ln as f64))
 }
 /// Calculates median of a vector
-pub fn median(x: Vec<f64>) -> f64 {
-    let a = sort(x);
+pub fn median(x: Vec<Option<f64>>) -> Option<f64> {
+    let a = sort(given(x));
     let ln = a.len();
-    if ln % 2 == 0 {
-        return (a[(ln / 2)] + a[(ln / 2) - 1]) / 2.0;
+    if ln == 0 {
+        None
+    } else if ln.is_multiple_of(2) {
+        Some((a[ln / 2] + a[(ln / 2) - 1]) / 2.0)
     } else {
-        let b = ((ln as f64) / 2.0).floor() as usize;
-        a[b]
+        Some(a[ln / 2])
     }
 }
 
+/// Drops missing inputs.
+fn given(x: Vec<Option<f64>>) -> Vec<f64> {
+    x.into_iter().flatten().collect()
+}
+
 /// Sorts list of floats
 fn sort(x: Vec<f64>) -> Vec<f64> {
     let mut a = x.clone();
-    a.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    a.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
     a
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck::{quickcheck, TestResult};
     use std::f64::consts::E;
     #[test]
     fn test_aikavali() {
@@ -205,23 +238,145 @@ mod tests {
         assert_eq!(0.03125, pow(4.0, -2.5));
     }
     #[test]
-    fn test_interpoloi() {}
-    // These two tests should be done better. Now we can't know what happens.
+    fn test_interpoloi() {
+        // Full points for the best result, none for the worst.
+        assert_eq!(10.0, interpoloi(100.0, 100.0, 10.0, 50.0, 0.0));
+        assert_eq!(5.0, interpoloi(75.0, 100.0, 10.0, 50.0, 0.0));
+        assert_eq!(0.0, interpoloi(50.0, 100.0, 10.0, 50.0, 0.0));
+        assert_eq!(2.5, interpoloi(60.0, 50.0, 0.0, 70.0, 5.0));
+        assert_eq!(7.0, interpoloi(2.0, 1.0, 5.0, 3.0, 9.0));
+    }
+    #[test]
+    fn test_interpoloi_clamps() {
+        assert_eq!(10.0, interpoloi(120.0, 100.0, 10.0, 50.0, 0.0));
+        assert_eq!(0.0, interpoloi(20.0, 100.0, 10.0, 50.0, 0.0));
+        assert_eq!(5.0, interpoloi(-1.0, 0.0, 5.0, 10.0, 1.0));
+        assert_eq!(1.0, interpoloi(11.0, 0.0, 5.0, 10.0, 1.0));
+        assert_eq!(3.0, interpoloi(7.0, 4.0, 3.0, 4.0, 8.0));
+    }
+
+    /// Reference interpolation written the other way round: sort the points
+    /// by x and clamp to the lower and the upper one.
+    fn reference(x: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        if x1 == x2 {
+            return y1;
+        }
+        let ((xl, yl), (xh, yh)) = if x1 < x2 {
+            ((x1, y1), (x2, y2))
+        } else {
+            ((x2, y2), (x1, y1))
+        };
+        if x <= xl {
+            yl
+        } else if x >= xh {
+            yh
+        } else {
+            yl + (yh - yl) * (x - xl) / (xh - xl)
+        }
+    }
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * f64::max(1.0, f64::max(a.abs(), b.abs()))
+    }
+    fn finite(v: &[f64]) -> bool {
+        v.iter().all(|v| v.is_finite())
+    }
+    #[test]
+    fn prop_interpoloi_reference() {
+        fn prop(x: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> TestResult {
+            if !finite(&[x, x1, y1, x2, y2]) {
+                return TestResult::discard();
+            }
+            let r = reference(x, x1, y1, x2, y2);
+            TestResult::from_bool(close(r, interpoloi(x, x1, y1, x2, y2)))
+        }
+        quickcheck(prop as fn(f64, f64, f64, f64, f64) -> TestResult);
+    }
+    #[test]
+    fn prop_interpoloi_bounded() {
+        fn prop(x: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> TestResult {
+            if !finite(&[x, x1, y1, x2, y2]) {
+                return TestResult::discard();
+            }
+            let y = interpoloi(x, x1, y1, x2, y2);
+            let (lo, hi) = (f64::min(y1, y2), f64::max(y1, y2));
+            let eps = 1e-9 * f64::max(1.0, f64::max(lo.abs(), hi.abs()));
+            TestResult::from_bool(lo - eps <= y && y <= hi + eps)
+        }
+        quickcheck(prop as fn(f64, f64, f64, f64, f64) -> TestResult);
+    }
+    #[test]
+    fn prop_interpoloi_endpoints() {
+        fn prop(x1: f64, y1: f64, x2: f64, y2: f64) -> TestResult {
+            if !finite(&[x1, y1, x2, y2]) || x1 == x2 {
+                return TestResult::discard();
+            }
+            TestResult::from_bool(
+                interpoloi(x1, x1, y1, x2, y2) == y1 && interpoloi(x2, x1, y1, x2, y2) == y2,
+            )
+        }
+        quickcheck(prop as fn(f64, f64, f64, f64) -> TestResult);
+    }
+    #[test]
+    fn prop_interpoloi_symmetric() {
+        fn prop(x: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> TestResult {
+            if !finite(&[x, x1, y1, x2, y2]) || x1 == x2 {
+                return TestResult::discard();
+            }
+            let a = interpoloi(x, x1, y1, x2, y2);
+            let b = interpoloi(x, x2, y2, x1, y1);
+            TestResult::from_bool(close(a, b))
+        }
+        quickcheck(prop as fn(f64, f64, f64, f64, f64) -> TestResult);
+    }
+    #[test]
+    fn prop_interpoloi_monotone() {
+        fn prop(a: f64, b: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> TestResult {
+            if !finite(&[a, b, x1, y1, x2, y2]) {
+                return TestResult::discard();
+            }
+            let (a, b) = if a <= b { (a, b) } else { (b, a) };
+            let (ya, yb) = (interpoloi(a, x1, y1, x2, y2), interpoloi(b, x1, y1, x2, y2));
+            // Moving x towards x2 moves the result towards y2.
+            let ok = if (x1 < x2) == (y1 <= y2) {
+                ya <= yb + 1e-9 * f64::max(1.0, yb.abs())
+            } else {
+                ya + 1e-9 * f64::max(1.0, ya.abs()) >= yb
+            };
+            TestResult::from_bool(ok)
+        }
+        quickcheck(prop as fn(f64, f64, f64, f64, f64, f64) -> TestResult);
+    }
+    fn some(x: Vec<f64>) -> Vec<Option<f64>> {
+        x.into_iter().map(Some).collect()
+    }
     #[test]
     fn test_max() {
-        assert_eq!(5.0, max(vec![5.0, 1.0, -10.0, 4.99999, 2.5]))
+        assert_eq!(Some(5.0), max(some(vec![5.0, 1.0, -10.0, 4.99999, 2.5])));
+        assert_eq!(Some(-1.0), max(vec![None, Some(-1.0), None]));
+        assert_eq!(None, max(vec![None, None]));
     }
     #[test]
     fn test_min() {
-        assert_eq!(-10.0, min(vec![5.0, 1.0, -10.0, 4.99999, 2.5]));
-        assert_eq!(0.0, min(vec![0.0, 1.0]));
+        assert_eq!(Some(-10.0), min(some(vec![5.0, 1.0, -10.0, 4.99999, 2.5])));
+        assert_eq!(Some(0.0), min(some(vec![0.0, 1.0])));
+        assert_eq!(Some(1.0), min(vec![None, Some(1.0)]));
+        assert_eq!(None, min(vec![]));
+    }
+    #[test]
+    fn test_sum() {
+        assert_eq!(Some(6.0), sum(vec![Some(1.0), None, Some(5.0)]));
+        assert_eq!(None, sum(vec![None]));
     }
     #[test]
     fn test_mean() {
-        assert_eq!(2.0, mean(vec![1.0, 2.0, 3.0]));
+        assert_eq!(Some(2.0), mean(some(vec![1.0, 2.0, 3.0])));
+        assert_eq!(Some(2.0), mean(vec![Some(1.0), None, Some(3.0), None]));
+        assert_eq!(None, mean(vec![None]));
     }
     #[test]
     fn test_median() {
-        assert_eq!(2.0, median(vec![1.0, 2.0, 3.0]));
+        assert_eq!(Some(2.0), median(some(vec![1.0, 2.0, 3.0])));
+        assert_eq!(Some(2.5), median(vec![Some(4.0), None, Some(1.0), Some(2.0), Some(3.0)]));
+        assert_eq!(None, median(vec![]));
     }
 }
@@ -0,0 +1,111 @@
+//! Parses kwp commands. Words of a command are separated by whitespace and
+//! optional arguments are left out from the end.
+
+use super::error::KwpError;
+
+/// Command sent to Kilac.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// `new connection db <dbtype> addr <addr>`
+    Connect { db: String, addr: String },
+    /// `new connection pid <id>`
+    Resume(String),
+    /// `info`
+    Info,
+    /// `calculate <comp> (<series>) (<task>)`
+    Calculate {
+        competition: String,
+        series: Option<String>,
+        task: Option<String>,
+    },
+    /// `verify <comp> <series> <task> (<subtask>)`
+    Verify {
+        competition: String,
+        series: String,
+        task: String,
+        subtask: Option<String>,
+    },
+    /// `cancel <id>`
+    Cancel(u64),
+}
+
+impl Command {
+    /// Parses a line, without the terminating newline.
+    pub fn parse(line: &str) -> Result<Command, KwpError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let own = |i: usize| words.get(i).map(|w| String::from(*w));
+        match words[..] {
+            ["new", "connection", "db", db, "addr", addr] => Ok(Command::Connect {
+                db: db.into(),
+                addr: addr.into(),
+            }),
+            ["new", "connection", "pid", pid] => Ok(Command::Resume(pid.into())),
+            ["info"] => Ok(Command::Info),
+            ["calculate", competition, ..] if words.len() <= 4 => Ok(Command::Calculate {
+                competition: competition.into(),
+                series: own(2),
+                task: own(3),
+            }),
+            ["verify", competition, series, task, ..] if words.len() <= 5 => Ok(Command::Verify {
+                competition: competition.into(),
+                series: series.into(),
+                task: task.into(),
+                subtask: own(4),
+            }),
+            ["cancel", id] => id
+                .parse()
+                .map(Command::Cancel)
+                .map_err(|_| KwpError::Command(format!("malformed id {}", id))),
+            [] => Err(KwpError::Command("empty command".into())),
+            [w, ..] => Err(KwpError::Command(format!("malformed command {}", w))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let p = |s: &str| Command::parse(s).unwrap();
+        assert_eq!(
+            Command::Connect {
+                db: "sqlite".into(),
+                addr: "kisa.db".into(),
+            },
+            p("new connection db sqlite addr kisa.db")
+        );
+        assert_eq!(Command::Resume("ab12".into()), p("new connection pid ab12"));
+        assert_eq!(Command::Info, p(" info "));
+        assert_eq!(Command::Cancel(3), p("cancel 3"));
+        assert_eq!(
+            Command::Calculate {
+                competition: "kisa".into(),
+                series: Some("sarja".into()),
+                task: None,
+            },
+            p("calculate kisa sarja")
+        );
+        assert_eq!(
+            Command::Verify {
+                competition: "kisa".into(),
+                series: "sarja".into(),
+                task: "t".into(),
+                subtask: Some("o".into()),
+            },
+            p("verify kisa sarja t o")
+        );
+    }
+    #[test]
+    fn test_malformed() {
+        let code = |s: &str| Command::parse(s).map_err(|e| e.code());
+        assert_eq!(Err(400), code(""));
+        assert_eq!(Err(400), code("new connection"));
+        assert_eq!(Err(400), code("calculate"));
+        assert_eq!(Err(400), code("calculate a b c d"));
+        assert_eq!(Err(400), code("verify kisa sarja"));
+        assert_eq!(Err(400), code("quit"));
+        assert_eq!(Err(400), code("cancel x"));
+    }
+}
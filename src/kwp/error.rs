@@ -0,0 +1,64 @@
+//! Errors of kwp commands. Codes continue the ones of `calc::error` and are
+//! listed in doc/protocol.md.
+
+use std::error::Error;
+use std::fmt;
+
+use competition::error::LoadError;
+
+/// Error of a command that is not an error of a formula.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KwpError {
+    /// Line is not a known command or has wrong arguments.
+    Command(String),
+    /// Command needs a connection, see `new connection`.
+    NotConnected,
+    /// Database could not be opened or read.
+    Db(String),
+    /// No such competition, series, task or subtask.
+    NotFound(String),
+    /// No session with this pid.
+    UnknownPid(String),
+    /// Sessions could not be read from or written to their file.
+    Store(String),
+}
+
+impl KwpError {
+    /// Stable error code of the error, like `KilaError::code`.
+    pub fn code(&self) -> u32 {
+        match *self {
+            KwpError::Command(_) => 400,
+            KwpError::NotConnected => 401,
+            KwpError::Db(_) => 402,
+            KwpError::NotFound(_) => 403,
+            KwpError::UnknownPid(_) => 404,
+            KwpError::Store(_) => 405,
+        }
+    }
+
+    /// The kwp error message `error <code> <explanation>`.
+    pub fn kwp(&self) -> String {
+        format!("error {} {}", self.code(), self)
+    }
+}
+
+impl fmt::Display for KwpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KwpError::Command(ref m) => write!(f, "{}", m),
+            KwpError::NotConnected => write!(f, "no connection"),
+            KwpError::Db(ref m) => write!(f, "database: {}", m),
+            KwpError::NotFound(ref m) => write!(f, "{} not found", m),
+            KwpError::UnknownPid(ref p) => write!(f, "unknown pid {}", p),
+            KwpError::Store(ref m) => write!(f, "session store: {}", m),
+        }
+    }
+}
+
+impl Error for KwpError {}
+
+impl From<LoadError> for KwpError {
+    fn from(e: LoadError) -> KwpError {
+        KwpError::Db(e.to_string())
+    }
+}
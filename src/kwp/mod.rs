@@ -0,0 +1,11 @@
+//! Kilac wire protocol (kwp), see doc/protocol.md. Commands are lines of
+//! text parsed by `command`, run against the database of a session by
+//! `session` and read from and answered to a stream by `server`, or over
+//! websockets by `websocket`.
+
+pub mod command;
+pub mod error;
+pub mod queue;
+pub mod server;
+pub mod session;
+pub mod websocket;
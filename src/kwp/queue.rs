@@ -0,0 +1,208 @@
+//! Queue of the commands that take long, calculations, run by a pool of
+//! workers. Every connection sends its calculations here and gets their
+//! messages back through a channel as each of them finishes, so the
+//! connection can go on with other commands meanwhile. Calculations still
+//! waiting can be cancelled.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use super::command::Command;
+use super::error::KwpError;
+use super::session::{calculate, with_sessions, Sessions};
+
+/// Queued command `id` of connection `conn`, run on session `pid`.
+#[derive(Debug)]
+pub struct Job {
+    pub conn: u64,
+    pub id: u64,
+    pub pid: String,
+    pub cmd: Command,
+    /// Where the messages of the command are sent.
+    pub reply: Sender<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    jobs: VecDeque<Job>,
+    connections: u64,
+    stopped: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+    sessions: Arc<Mutex<Sessions>>,
+}
+
+/// Queue of jobs and the workers running them. Dropping the queue stops the
+/// workers once the jobs already queued are done.
+#[derive(Debug)]
+pub struct Queue {
+    shared: Arc<Shared>,
+}
+
+/// Sends the messages of command `id` as `id <id> <message>`. Errors of
+/// sending are ignored, as they only mean the connection has closed.
+pub fn send(reply: &Sender<String>, id: u64, res: Result<Vec<String>, KwpError>) {
+    match res {
+        Ok(messages) => {
+            for m in messages {
+                let _ = reply.send(format!("id {} {}", id, m));
+            }
+        }
+        Err(e) => {
+            let _ = reply.send(format!("id {} {}", id, e.kwp()));
+        }
+    }
+}
+
+impl Queue {
+    /// Queue run by `workers` threads on `sessions`. Without workers jobs
+    /// stay queued.
+    pub fn new(workers: usize, sessions: Arc<Mutex<Sessions>>) -> Queue {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            ready: Condvar::new(),
+            sessions,
+        });
+        for _ in 0..workers {
+            let shared = shared.clone();
+            thread::spawn(move || work(&shared));
+        }
+        Queue { shared }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn sessions(&self) -> &Mutex<Sessions> {
+        &self.shared.sessions
+    }
+
+    /// Number for a new connection, to tell its jobs apart.
+    pub fn connect(&self) -> u64 {
+        let mut state = self.state();
+        state.connections += 1;
+        state.connections
+    }
+
+    pub fn push(&self, job: Job) {
+        self.state().jobs.push_back(job);
+        self.shared.ready.notify_one();
+    }
+
+    /// Removes command `id` of connection `conn` from the queue. Returns
+    /// it if it had not yet been started.
+    pub fn cancel(&self, conn: u64, id: u64) -> Option<Job> {
+        let mut state = self.state();
+        let i = state.jobs.iter().position(|j| j.conn == conn && j.id == id)?;
+        state.jobs.remove(i)
+    }
+
+    /// Number of jobs waiting for a worker.
+    pub fn len(&self) -> usize {
+        self.state().jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        self.state().stopped = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+/// Runs jobs until the queue is stopped and empty.
+fn work(shared: &Shared) {
+    loop {
+        let job = {
+            let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            loop {
+                if let Some(job) = state.jobs.pop_front() {
+                    break job;
+                }
+                if state.stopped {
+                    return;
+                }
+                state = shared.ready.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        };
+        send(&job.reply, job.id, run(&shared.sessions, &job));
+    }
+}
+
+fn run(sessions: &Mutex<Sessions>, job: &Job) -> Result<Vec<String>, KwpError> {
+    // Only the database of the session stays locked while calculating, so
+    // other sessions are not held up.
+    let db = with_sessions(sessions, |s| Ok(s.resume(&job.pid)?.db()))?;
+    let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+    match job.cmd {
+        Command::Calculate {
+            ref competition,
+            ref series,
+            ref task,
+        } => calculate(
+            &mut db,
+            competition,
+            series.as_ref().map(|s| &s[..]),
+            task.as_ref().map(|t| &t[..]),
+        ),
+        _ => Err(KwpError::Command("command can not be queued".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Job calculating competition kisa and where its messages go.
+    fn job(conn: u64, id: u64, pid: &str) -> (Job, Receiver<String>) {
+        let (tx, rx) = channel();
+        let job = Job {
+            conn,
+            id,
+            pid: pid.into(),
+            cmd: Command::parse("calculate kisa").unwrap(),
+            reply: tx,
+        };
+        (job, rx)
+    }
+
+    #[test]
+    fn test_cancel() {
+        let queue = Queue::new(0, Arc::new(Mutex::new(Sessions::new())));
+        let conn = queue.connect();
+        assert_eq!(conn + 1, queue.connect());
+        let (a, _) = job(conn, 1, "x");
+        let (b, _) = job(conn, 2, "x");
+        queue.push(a);
+        queue.push(b);
+        assert_eq!(2, queue.len());
+        assert!(queue.cancel(conn + 1, 1).is_none());
+        assert_eq!(Some(1), queue.cancel(conn, 1).map(|j| j.id));
+        assert!(queue.cancel(conn, 1).is_none());
+        assert_eq!(1, queue.len());
+    }
+    #[test]
+    fn test_work() {
+        let sessions = Arc::new(Mutex::new(Sessions::new()));
+        let pid = sessions.lock().unwrap().open("sqlite", ":memory:").unwrap();
+        let queue = Queue::new(2, sessions);
+        let (a, rx) = job(1, 1, &pid);
+        let (b, other) = job(1, 2, "x");
+        queue.push(a);
+        queue.push(b);
+        assert_eq!("id 1 error 403 competition kisa not found", rx.recv().unwrap());
+        assert_eq!("id 2 error 404 unknown pid x", other.recv().unwrap());
+    }
+}
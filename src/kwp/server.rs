@@ -0,0 +1,181 @@
+//! Serves kwp over a stream of lines. Every command gets an id, which is
+//! sent back at once with `cmd <id> queued`, and every message of the
+//! command is then sent as `id <id> <message>`. Calculations are run by
+//! the workers of `queue`, so their messages may come after the ones of
+//! later commands. See `websocket` for serving kwp over websockets.
+
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use super::command::Command;
+use super::error::KwpError;
+use super::queue::{send, Job, Queue};
+use super::session::{with_sessions, Connection};
+
+/// Runs the commands of one connection and numbers them. Messages are
+/// sent to `reply`.
+#[derive(Debug)]
+pub struct Handler<'a> {
+    queue: &'a Queue,
+    conn: Connection,
+    /// Number of the connection in the queue.
+    key: u64,
+    id: u64,
+    reply: Sender<String>,
+}
+
+impl<'a> Handler<'a> {
+    pub fn new(queue: &'a Queue, reply: Sender<String>) -> Handler<'a> {
+        Handler {
+            queue,
+            conn: Connection::new(),
+            key: queue.connect(),
+            id: 0,
+            reply,
+        }
+    }
+
+    /// Runs command `line`, or queues it if it is a calculation. Empty
+    /// lines are skipped. Fails if the messages can no longer be sent.
+    pub fn handle(&mut self, line: &str) -> io::Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        self.id += 1;
+        let id = self.id;
+        self.reply
+            .send(format!("cmd {} queued", id))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"))?;
+        match Command::parse(line).and_then(|cmd| self.run(id, cmd)) {
+            Ok(None) => {}
+            Ok(Some(messages)) => send(&self.reply, id, Ok(messages)),
+            Err(e) => send(&self.reply, id, Err(e)),
+        }
+        Ok(())
+    }
+
+    /// Runs command `id`. Returns its messages, or nothing if it was queued.
+    fn run(&mut self, id: u64, cmd: Command) -> Result<Option<Vec<String>>, KwpError> {
+        let sessions = self.queue.sessions();
+        match cmd {
+            Command::Calculate { .. } => {
+                let pid = self.conn.pid.clone().ok_or(KwpError::NotConnected)?;
+                with_sessions(sessions, |s| s.resume(&pid).map(|_| ()))?;
+                self.queue.push(Job {
+                    conn: self.key,
+                    id,
+                    pid,
+                    cmd,
+                    reply: self.reply.clone(),
+                });
+                Ok(None)
+            }
+            Command::Cancel(queued) => match self.queue.cancel(self.key, queued) {
+                Some(job) => {
+                    send(&job.reply, queued, Ok(vec!["cancelled".into()]));
+                    Ok(Some(vec![format!("cancelled {}", queued)]))
+                }
+                None => Err(KwpError::NotFound(format!("queued command {}", queued))),
+            },
+            Command::Info => Ok(Some(vec![self.conn.info(sessions, self.queue.len())?])),
+            _ => self.conn.execute(sessions, &cmd).map(Some),
+        }
+    }
+}
+
+/// Serves one connection until `input` ends and the queued commands of it
+/// are done.
+pub fn serve<R: BufRead, W: Write + Send>(input: R, mut output: W, queue: &Queue) -> io::Result<()> {
+    let (tx, rx) = channel::<String>();
+    thread::scope(|scope| {
+        let writer = scope.spawn(move || -> io::Result<()> {
+            for reply in rx {
+                writeln!(output, "{}", reply)?;
+                output.flush()?;
+            }
+            Ok(())
+        });
+        let mut handler = Handler::new(queue, tx);
+        let mut read = Ok(());
+        for line in input.lines() {
+            read = line.and_then(|l| handler.handle(&l));
+            if read.is_err() {
+                break;
+            }
+        }
+        drop(handler);
+        let written = writer.join().unwrap_or_else(|_| Err(io::Error::other("writer panicked")));
+        written.and(read)
+    })
+}
+
+/// Accepts TCP connections on `addr` and serves each in its own thread.
+/// Sessions and the queue are shared by the connections.
+pub fn listen<A: ToSocketAddrs>(addr: A, queue: Arc<Queue>) -> io::Result<()> {
+    for stream in TcpListener::bind(addr)?.incoming() {
+        let stream = stream?;
+        let queue = queue.clone();
+        thread::spawn(move || -> io::Result<()> {
+            let input = BufReader::new(stream.try_clone()?);
+            serve(input, stream, &queue)
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kwp::session::Sessions;
+    use std::sync::Mutex;
+
+    fn lines(queue: &Queue, input: &str) -> Vec<String> {
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, queue).unwrap();
+        String::from_utf8(output).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_serve() {
+        let queue = Queue::new(2, Arc::new(Mutex::new(Sessions::new())));
+        let input = "info\n\nnew connection db sqlite addr :memory:\ncalculate kisa\nfoo\n";
+        let lines = lines(&queue, input);
+        assert_eq!(8, lines.len());
+        assert_eq!("cmd 1 queued", lines[0]);
+        assert_eq!("id 1 error 401 no connection", lines[1]);
+        assert_eq!("cmd 2 queued", lines[2]);
+        assert!(lines[3].starts_with("id 2 connection estb pid "));
+        assert_eq!("cmd 3 queued", lines[4]);
+        // The calculation is queued, so it may finish after the next command.
+        assert!(lines.contains(&"id 3 error 403 competition kisa not found".into()));
+        assert!(lines.contains(&"id 4 error 400 malformed command foo".into()));
+    }
+    #[test]
+    fn test_queue() {
+        // Without workers calculations stay queued until cancelled.
+        let queue = Queue::new(0, Arc::new(Mutex::new(Sessions::new())));
+        let input = "new connection db sqlite addr :memory:\ncalculate kisa\ncalculate kisa\n\
+                     info\ncancel 2\ncancel 2\ncancel 3\n";
+        let mut lines = lines(&queue, input);
+        assert!(lines[5].starts_with("id 4 info 0 ") && lines[5].ends_with(" 2"));
+        lines.drain(..6);
+        assert_eq!(
+            vec![
+                "cmd 5 queued",
+                "id 2 cancelled",
+                "id 5 cancelled 2",
+                "cmd 6 queued",
+                "id 6 error 403 queued command 2 not found",
+                "cmd 7 queued",
+                "id 3 cancelled",
+                "id 7 cancelled 3",
+            ],
+            lines
+        );
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,574 @@
+//! Sessions of kwp. A session is opened with `new connection db` and holds
+//! the database it was opened with. It is identified by its pid, so later
+//! connections can continue it with `new connection pid`.
+//!
+//! Sessions can be kept in a file, so they survive restarts of the server,
+//! and expire when they have not been used for a while.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use getrandom;
+use serde_json;
+
+use calc::check::check;
+use competition::ctx::CompetitionCtx;
+use competition::sqlite::Db;
+use competition::Competition;
+
+use super::command::Command;
+use super::error::KwpError;
+
+/// Session opened by a client.
+#[derive(Debug)]
+pub struct Session {
+    pub pid: String,
+    /// When the session was opened.
+    pub created: SystemTime,
+    /// When the session was last used.
+    pub used: SystemTime,
+    dbtype: String,
+    addr: String,
+    /// Locked only for a command, so workers of `queue` can use it while
+    /// the sessions are unlocked.
+    db: Arc<Mutex<Db>>,
+}
+
+/// Session as kept in the file of `Sessions`. Times are in seconds since
+/// the epoch.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredSession {
+    pid: String,
+    db: String,
+    addr: String,
+    created: u64,
+    used: u64,
+}
+
+/// Every open session by pid.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: HashMap<String, Session>,
+    /// File the sessions are kept in, if any.
+    path: Option<PathBuf>,
+    /// Whether sessions were opened or removed since the last `store`.
+    changed: bool,
+    /// Number of the last `store`.
+    version: u64,
+    /// Number of the last `Store` written to the file. Locked while
+    /// writing, so only the newest one gets written.
+    written: Arc<Mutex<u64>>,
+    /// Sessions not used for longer than this expire. They never expire if
+    /// not given.
+    pub max_idle: Option<Duration>,
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn store_err(path: &Path, e: &dyn ToString) -> KwpError {
+    KwpError::Store(format!("{}: {}", path.display(), e.to_string()))
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions::default()
+    }
+
+    /// Sessions kept in file `path`, with the sessions already in it. The
+    /// file is created when the first session is opened. Sessions whose
+    /// database can no longer be opened are dropped. In-memory sqlite
+    /// databases are empty again after a restart. Times of use are not
+    /// written on every use, so loaded sessions count as used now.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Sessions, KwpError> {
+        let path = path.as_ref();
+        let mut sessions = Sessions {
+            path: Some(path.to_path_buf()),
+            ..Sessions::default()
+        };
+        let stored: Vec<StoredSession> = match fs::read_to_string(path) {
+            Ok(s) => serde_json::from_str(&s).map_err(|e| store_err(path, &e))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(store_err(path, &e)),
+        };
+        for s in stored {
+            if let Ok(db) = open_db(&s.db, &s.addr) {
+                sessions.sessions.insert(
+                    s.pid.clone(),
+                    Session {
+                        pid: s.pid,
+                        created: UNIX_EPOCH + Duration::from_secs(s.created),
+                        used: SystemTime::now().max(UNIX_EPOCH + Duration::from_secs(s.used)),
+                        dbtype: s.db,
+                        addr: s.addr,
+                        db: Arc::new(Mutex::new(db)),
+                    },
+                );
+            }
+        }
+        Ok(sessions)
+    }
+
+    /// Sessions to write to their file, if they have one and sessions have
+    /// been opened or removed since the last call. Written with
+    /// `Store::write` once the sessions are unlocked.
+    pub fn store(&mut self) -> Option<Store> {
+        let path = match self.path {
+            Some(ref p) if self.changed => p.clone(),
+            _ => return None,
+        };
+        self.changed = false;
+        self.version += 1;
+        let mut stored: Vec<StoredSession> = self
+            .sessions
+            .values()
+            .map(|s| StoredSession {
+                pid: s.pid.clone(),
+                db: s.dbtype.clone(),
+                addr: s.addr.clone(),
+                created: secs(s.created),
+                used: secs(s.used),
+            })
+            .collect();
+        stored.sort_by(|a, b| a.pid.cmp(&b.pid));
+        Some(Store {
+            path,
+            stored,
+            version: self.version,
+            written: self.written.clone(),
+        })
+    }
+
+    /// Removes the sessions that have not been used for longer than
+    /// `max_idle`. Returns whether any were removed.
+    pub fn expire(&mut self) -> bool {
+        let max_idle = match self.max_idle {
+            Some(d) => d,
+            None => return false,
+        };
+        let now = SystemTime::now();
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|_, s| now.duration_since(s.used).map(|idle| idle <= max_idle).unwrap_or(true));
+        let expired = self.sessions.len() != before;
+        self.changed |= expired;
+        expired
+    }
+
+    /// Opens a session on database `addr` of type `dbtype`. Returns the pid.
+    pub fn open(&mut self, dbtype: &str, addr: &str) -> Result<String, KwpError> {
+        let db = open_db(dbtype, addr)?;
+        self.add(dbtype, addr, db)
+    }
+
+    /// Adds a session on database `db`, opened from `addr` of type `dbtype`.
+    /// Returns the pid.
+    pub fn add(&mut self, dbtype: &str, addr: &str, db: Db) -> Result<String, KwpError> {
+        self.expire();
+        let pid = self.new_pid()?;
+        let now = SystemTime::now();
+        self.sessions.insert(
+            pid.clone(),
+            Session {
+                pid: pid.clone(),
+                created: now,
+                used: now,
+                dbtype: dbtype.into(),
+                addr: addr.into(),
+                db: Arc::new(Mutex::new(db)),
+            },
+        );
+        self.changed = true;
+        Ok(pid)
+    }
+
+    /// Unguessable, unique pid of 128 bits, taken from the random number
+    /// generator of the operating system.
+    fn new_pid(&self) -> Result<String, KwpError> {
+        loop {
+            let mut bytes = [0u8; 16];
+            getrandom::getrandom(&mut bytes).map_err(|e| KwpError::Store(format!("no random pid: {}", e)))?;
+            let pid: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            if !self.sessions.contains_key(&pid) {
+                return Ok(pid);
+            }
+        }
+    }
+
+    /// Session `pid` for a command, marked as used. Sessions that have
+    /// expired are not found. The time of use is only kept in memory, only
+    /// the expired sessions are stored.
+    pub fn resume(&mut self, pid: &str) -> Result<&mut Session, KwpError> {
+        self.expire();
+        let s = self.sessions.get_mut(pid).ok_or_else(|| KwpError::UnknownPid(pid.into()))?;
+        s.used = SystemTime::now();
+        Ok(s)
+    }
+
+    pub fn get(&self, pid: &str) -> Option<&Session> {
+        self.sessions.get(pid)
+    }
+
+    pub fn get_mut(&mut self, pid: &str) -> Option<&mut Session> {
+        self.sessions.get_mut(pid)
+    }
+}
+
+/// Sessions as written to their file by `Store::write`.
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+    stored: Vec<StoredSession>,
+    version: u64,
+    written: Arc<Mutex<u64>>,
+}
+
+impl Store {
+    /// Writes the sessions to their file, unless a newer `Store` of them
+    /// has already been written.
+    pub fn write(self) -> Result<(), KwpError> {
+        let mut written = self.written.lock().unwrap_or_else(|e| e.into_inner());
+        if *written >= self.version {
+            return Ok(());
+        }
+        let path = &self.path;
+        let json = serde_json::to_string_pretty(&self.stored).map_err(|e| store_err(path, &e))?;
+        // Written next to the file and renamed, so a crash never leaves a
+        // half written file.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| store_err(path, &e))?;
+        *written = self.version;
+        Ok(())
+    }
+}
+
+/// Runs `f` with `sessions` locked and then writes the sessions it opened
+/// or removed to their file, after unlocking them so other threads are not
+/// held up by the file. A panic while they were locked leaves them usable.
+pub fn with_sessions<T, F>(sessions: &Mutex<Sessions>, f: F) -> Result<T, KwpError>
+where
+    F: FnOnce(&mut Sessions) -> Result<T, KwpError>,
+{
+    let (result, store) = {
+        let mut sessions = sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let result = f(&mut sessions);
+        (result, sessions.store())
+    };
+    if let Some(store) = store {
+        store.write()?;
+    }
+    result
+}
+
+fn open_db(dbtype: &str, addr: &str) -> Result<Db, KwpError> {
+    match dbtype {
+        "sqlite" => Ok(Db::open(addr)?),
+        _ => Err(KwpError::Db(format!("unknown database type {}", dbtype))),
+    }
+}
+
+impl Session {
+    /// Database of the session, shared with the commands running on it.
+    pub fn db(&self) -> Arc<Mutex<Db>> {
+        self.db.clone()
+    }
+}
+
+fn competition(db: &Db, name: &str) -> Result<Competition, KwpError> {
+    if !db.competitions()?.iter().any(|c| c == name) {
+        return Err(KwpError::NotFound(format!("competition {}", name)));
+    }
+    Ok(db.load(name)?)
+}
+
+/// Calculates competition `name` and returns a message
+/// `points <series> <task> <subtask> <team> <points>` for every subtask
+/// of series `series` and task `task`, or of all of them if not given.
+/// Points are a number, `-` if empty or an error.
+pub fn calculate(db: &mut Db, name: &str, series: Option<&str>, task: Option<&str>) -> Result<Vec<String>, KwpError> {
+    let c = competition(db, name)?;
+    let si = match series {
+        Some(s) => Some(c.find_series(s).ok_or_else(|| KwpError::NotFound(format!("series {}", s)))?),
+        None => None,
+    };
+    let ti = match (si, task) {
+        (Some(si), Some(t)) => {
+            Some(c.series[si].find_task(t).ok_or_else(|| KwpError::NotFound(format!("task {}", t)))?)
+        }
+        _ => None,
+    };
+    let mut res = Vec::new();
+    for p in db.calculate_in(name, si, ti)? {
+        let s = &c.series[p.series];
+        let t = &s.tasks[p.task];
+        let v = match p.points {
+            Ok(Some(v)) => v.to_string(),
+            Ok(None) => "-".into(),
+            Err(e) => e.kwp(),
+        };
+        res.push(format!(
+            "points {} {} {} {} {}",
+            s.name, t.name, t.subtasks[p.subtask].name, s.teams[p.team].number, v
+        ));
+    }
+    res.push(format!("calculated {}", res.len()));
+    Ok(res)
+}
+
+/// Checks the formulas of task `task`, or only of its subtask `subtask`,
+/// against the inputs of the competition, without calculating them, see
+/// `calc::check`. Returns `compiled <subtask> <formula>` with the formula
+/// in normal form, or `error <code> <subtask>: <explanation>` for every
+/// error of the formula.
+fn verify(db: &Db, name: &str, series: &str, task: &str, subtask: Option<&str>) -> Result<Vec<String>, KwpError> {
+    let c = competition(db, name)?;
+    let si = c.find_series(series)
+        .ok_or_else(|| KwpError::NotFound(format!("series {}", series)))?;
+    let ti = c.series[si]
+        .find_task(task)
+        .ok_or_else(|| KwpError::NotFound(format!("task {}", task)))?;
+    let t = &c.series[si].tasks[ti];
+    let subtasks = match subtask {
+        Some(o) => vec![t.find_subtask(o).ok_or_else(|| KwpError::NotFound(format!("subtask {}", o)))?],
+        None => (0..t.subtasks.len()).collect(),
+    };
+    let mut res = Vec::new();
+    for oi in subtasks {
+        let o = &t.subtasks[oi];
+        let ctx = CompetitionCtx::new(&c, si, ti, oi, 0);
+        match check(&o.formula, |g| ctx.shape(g)) {
+            Ok(formula) => res.push(format!("compiled {} {}", o.name, formula)),
+            Err(errors) => {
+                for e in errors {
+                    res.push(format!("error {} {}: {}", e.code(), o.name, e));
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// State of a connection to Kilac.
+#[derive(Debug)]
+pub struct Connection {
+    /// Pid of the session of the connection.
+    pub pid: Option<String>,
+    opened: Instant,
+}
+
+impl Default for Connection {
+    fn default() -> Connection {
+        Connection::new()
+    }
+}
+
+impl Connection {
+    pub fn new() -> Connection {
+        Connection {
+            pid: None,
+            opened: Instant::now(),
+        }
+    }
+
+    /// Message `info <time open> <first connected> <pid> <queued>` about
+    /// the connection, with `queued` commands waiting in the queue.
+    pub fn info(&self, sessions: &Mutex<Sessions>, queued: usize) -> Result<String, KwpError> {
+        let pid = self.pid.as_ref().ok_or(KwpError::NotConnected)?;
+        let created = with_sessions(sessions, |s| Ok(secs(s.resume(pid)?.created)))?;
+        Ok(format!("info {} {} {} {}", self.opened.elapsed().as_secs(), created, pid, queued))
+    }
+
+    /// Runs `cmd` at once and returns the messages to send back. Nothing is
+    /// queued, so `info` reports an empty queue and `cancel` finds nothing.
+    /// `sessions` are locked only to find the session, never while a
+    /// database is opened or used.
+    pub fn execute(&mut self, sessions: &Mutex<Sessions>, cmd: &Command) -> Result<Vec<String>, KwpError> {
+        match *cmd {
+            Command::Connect { ref db, ref addr } => {
+                let opened = open_db(db, addr)?;
+                let pid = with_sessions(sessions, |s| s.add(db, addr, opened))?;
+                self.pid = Some(pid.clone());
+                return Ok(vec![format!("connection estb pid {}", pid)]);
+            }
+            Command::Resume(ref pid) => {
+                with_sessions(sessions, |s| s.resume(pid).map(|_| ()))?;
+                self.pid = Some(pid.clone());
+                return Ok(vec![format!("connection estb pid {}", pid)]);
+            }
+            Command::Info => return Ok(vec![self.info(sessions, 0)?]),
+            _ => {}
+        }
+        let pid = self.pid.as_ref().ok_or(KwpError::NotConnected)?;
+        let db = with_sessions(sessions, |s| Ok(s.resume(pid)?.db()))?;
+        let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+        match *cmd {
+            Command::Calculate {
+                ref competition,
+                ref series,
+                ref task,
+            } => calculate(
+                &mut db,
+                competition,
+                series.as_ref().map(|s| &s[..]),
+                task.as_ref().map(|t| &t[..]),
+            ),
+            Command::Verify {
+                ref competition,
+                ref series,
+                ref task,
+                ref subtask,
+            } => verify(&db, competition, series, task, subtask.as_ref().map(|s| &s[..])),
+            Command::Cancel(id) => Err(KwpError::NotFound(format!("queued command {}", id))),
+            Command::Connect { .. } | Command::Resume(_) | Command::Info => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use competition::json;
+    use std::env;
+    use std::process;
+    use std::thread;
+
+    const EXAMPLE: &str = include_str!("../../doc/competition.json");
+
+    fn run(conn: &mut Connection, sessions: &Mutex<Sessions>, line: &str) -> Result<Vec<String>, u32> {
+        conn.execute(sessions, &Command::parse(line).unwrap()).map_err(|e| e.code())
+    }
+
+    /// Session on a database in memory with the example competition.
+    fn session() -> (Connection, Mutex<Sessions>) {
+        let sessions = Mutex::new(Sessions::new());
+        let mut conn = Connection::new();
+        run(&mut conn, &sessions, "new connection db sqlite addr :memory:").unwrap();
+        let c = json::from_str(EXAMPLE).unwrap();
+        let pid = conn.pid.clone().unwrap();
+        sessions.lock().unwrap().get(&pid).unwrap().db().lock().unwrap().save(&c).unwrap();
+        (conn, sessions)
+    }
+
+    #[test]
+    fn test_connection() {
+        let (mut conn, sessions) = session();
+        let pid = conn.pid.clone().unwrap();
+        let info = run(&mut conn, &sessions, "info").unwrap();
+        assert!(info[0].starts_with("info 0 ") && info[0].ends_with(&format!(" {} 0", pid)));
+        let mut other = Connection::new();
+        assert_eq!(Err(401), run(&mut other, &sessions, "info"));
+        assert_eq!(Err(404), run(&mut other, &sessions, "new connection pid x"));
+        let resumed = run(&mut other, &sessions, &format!("new connection pid {}", pid));
+        assert_eq!(Ok(vec![format!("connection estb pid {}", pid)]), resumed);
+        assert_eq!(Err(402), run(&mut other, &sessions, "new connection db mysql addr x"));
+    }
+    #[test]
+    fn test_store() {
+        let path = env::temp_dir().join(format!("kwp-sessions-{}.json", process::id()));
+        let sessions = Mutex::new(Sessions::load(&path).unwrap());
+        let mut conn = Connection::new();
+        run(&mut conn, &sessions, "new connection db sqlite addr :memory:").unwrap();
+        let pid = conn.pid.clone().unwrap();
+        assert_eq!(32, pid.len());
+        let created = sessions.lock().unwrap().get(&pid).unwrap().created;
+
+        // Restarted server.
+        let sessions = Mutex::new(Sessions::load(&path).unwrap());
+        let mut conn = Connection::new();
+        run(&mut conn, &sessions, &format!("new connection pid {}", pid)).unwrap();
+        assert_eq!(secs(created), secs(sessions.lock().unwrap().get(&pid).unwrap().created));
+        let stored = fs::read_to_string(&path).unwrap();
+        fs::write(&path, "").unwrap();
+        run(&mut conn, &sessions, "info").unwrap();
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+        fs::write(&path, stored).unwrap();
+
+        sessions.lock().unwrap().max_idle = Some(Duration::from_secs(3600));
+        sessions.lock().unwrap().get_mut(&pid).unwrap().used -= Duration::from_secs(7200);
+        assert_eq!(Err(404), run(&mut conn, &sessions, "info"));
+        assert!(Sessions::load(&path).unwrap().get(&pid).is_none());
+        fs::remove_file(&path).unwrap();
+
+        fs::write(&path, "x").unwrap();
+        assert_eq!(405, Sessions::load(&path).unwrap_err().code());
+        fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_store_order() {
+        let path = env::temp_dir().join(format!("kwp-sessions-order-{}.json", process::id()));
+        let mut sessions = Sessions::load(&path).unwrap();
+        assert!(sessions.store().is_none());
+        sessions.open("sqlite", ":memory:").unwrap();
+        let older = sessions.store().unwrap();
+        sessions.open("sqlite", ":memory:").unwrap();
+        let newer = sessions.store().unwrap();
+        assert!(sessions.store().is_none());
+        // Written in the wrong order by two threads.
+        newer.write().unwrap();
+        older.write().unwrap();
+        assert_eq!(2, Sessions::load(&path).unwrap().sessions.len());
+        fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_calculate() {
+        let (mut conn, sessions) = session();
+        assert_eq!(
+            Ok(vec![
+                "points Sudenpennut ensiapu pisteet 1 10".into(),
+                "points Sudenpennut ensiapu pisteet 2 5".into(),
+                "points Sudenpennut ensiapu pisteet 3 7.5".into(),
+                "calculated 3".into(),
+            ]),
+            run(&mut conn, &sessions, "calculate Esimerkkikisa Sudenpennut ensiapu")
+        );
+        let db = sessions.lock().unwrap().get(&conn.pid.clone().unwrap()).unwrap().db();
+        assert_eq!(3, db.lock().unwrap().points("Esimerkkikisa").unwrap().len());
+        assert_eq!(7, run(&mut conn, &sessions, "calculate Esimerkkikisa").unwrap().len());
+        assert_eq!(Err(403), run(&mut conn, &sessions, "calculate x"));
+        assert_eq!(Err(403), run(&mut conn, &sessions, "calculate Esimerkkikisa x"));
+    }
+    #[test]
+    fn test_verify() {
+        let (mut conn, sessions) = session();
+        assert_eq!(
+            Ok(vec!["compiled pisteet a / max(muk.a) * 10".into()]),
+            run(&mut conn, &sessions, "verify Esimerkkikisa Sudenpennut ensiapu")
+        );
+        let pid = conn.pid.clone().unwrap();
+        let mut c = json::from_str(EXAMPLE).unwrap();
+        c.series[0].tasks[1].subtasks[0].formula = "abs(.a)/max(.b)".into();
+        sessions.lock().unwrap().get(&pid).unwrap().db().lock().unwrap().save(&c).unwrap();
+        assert_eq!(
+            Ok(vec![
+                "error 200 pisteet: expected number but got list at 4..6".into(),
+                "error 104 pisteet: unknown identifier .b at 12..14".into(),
+            ]),
+            run(&mut conn, &sessions, "verify Esimerkkikisa Sudenpennut ensiapu pisteet")
+        );
+        assert_eq!(Err(403), run(&mut conn, &sessions, "verify Esimerkkikisa Sudenpennut ensiapu x"));
+    }
+    #[test]
+    fn test_busy_db() {
+        let (mut conn, sessions) = session();
+        let pid = conn.pid.clone().unwrap();
+        let db = sessions.lock().unwrap().get(&pid).unwrap().db();
+        // A worker calculating on the database of the session.
+        let busy = db.lock().unwrap();
+        thread::scope(|scope| {
+            let verified = scope.spawn(|| run(&mut conn, &sessions, "verify Esimerkkikisa Sudenpennut ensiapu"));
+            thread::sleep(Duration::from_millis(50));
+            // Other sessions are not held up meanwhile.
+            let mut other = Connection::new();
+            assert!(run(&mut other, &sessions, "new connection db sqlite addr :memory:").is_ok());
+            drop(busy);
+            assert_eq!(1, verified.join().unwrap().unwrap().len());
+        });
+    }
+}
@@ -0,0 +1,498 @@
+//! Serves kwp over websockets (RFC 6455). After the opening handshake every
+//! text message from the client holds one or more newline terminated
+//! commands, and every reply line is sent back as a text message of its
+//! own, newline included. Pings are answered with pongs and a close is
+//! answered with a close, after which the connection ends.
+//!
+//! Binary messages are not part of kwp and close the connection, and so do
+//! frames that are not masked, as the RFC requires of clients.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1_smol::Sha1;
+
+use super::queue::Queue;
+use super::server::Handler;
+
+/// Appended to the key of the client to get the accept key.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest message accepted from a client. Commands are short.
+const MAX_MESSAGE: usize = 1 << 16;
+
+/// Longest line and most headers accepted in the handshake.
+const MAX_LINE: usize = 8 << 10;
+const MAX_HEADERS: usize = 100;
+
+/// Error of a frame longer than `MAX_MESSAGE`, see `too_long`.
+#[derive(Debug)]
+struct TooLong;
+
+impl fmt::Display for TooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frame longer than {} bytes", MAX_MESSAGE)
+    }
+}
+
+impl Error for TooLong {}
+
+/// Whether `e` is the error of reading a frame longer than `MAX_MESSAGE`.
+pub fn too_long(e: &io::Error) -> bool {
+    e.get_ref().map(|e| e.is::<TooLong>()).unwrap_or(false)
+}
+
+/// Kind of a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(b: u8) -> Option<Opcode> {
+        match b {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// Frame of the websocket protocol, unmasked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Last frame of a message.
+    pub fin: bool,
+    pub opcode: Opcode,
+    /// Whether the frame was masked when read.
+    pub masked: bool,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Single frame message.
+    pub fn new(opcode: Opcode, payload: &[u8]) -> Frame {
+        Frame {
+            fin: true,
+            opcode,
+            masked: false,
+            payload: payload.to_vec(),
+        }
+    }
+}
+
+fn invalid(m: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, m)
+}
+
+/// Value of `Sec-WebSocket-Accept` for `Sec-WebSocket-Key` `key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key.trim().as_bytes());
+    sha.update(GUID.as_bytes());
+    BASE64.encode(sha.digest().bytes())
+}
+
+/// Reads a line of the handshake into `line`. Fails if it is longer than
+/// `MAX_LINE`.
+fn read_line<R: BufRead>(input: &mut R, line: &mut String) -> io::Result<()> {
+    let n = input.by_ref().take(MAX_LINE as u64).read_line(line)?;
+    if n == MAX_LINE && !line.ends_with('\n') {
+        return Err(invalid("handshake line too long"));
+    }
+    Ok(())
+}
+
+/// Answers a request that is not a websocket upgrade.
+fn bad_request<W: Write>(output: &mut W, e: io::Error) -> io::Result<()> {
+    write!(output, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+    output.flush()?;
+    Err(e)
+}
+
+/// Reads the opening handshake of a client from `input` and answers it.
+/// Requests that are not websocket upgrades get `400 Bad Request`, and so
+/// do requests with lines longer than `MAX_LINE` or more than
+/// `MAX_HEADERS` headers.
+pub fn handshake<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<()> {
+    let mut request = String::new();
+    if let Err(e) = read_line(input, &mut request) {
+        return bad_request(output, e);
+    }
+    let mut key = None;
+    let mut upgrade = false;
+    let mut version = false;
+    let mut headers = 0;
+    loop {
+        let mut line = String::new();
+        if let Err(e) = read_line(input, &mut line) {
+            return bad_request(output, e);
+        }
+        if line.trim().is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return bad_request(output, invalid("too many headers"));
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().unwrap_or("").trim();
+        match &name[..] {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-key" => key = Some(value.to_string()),
+            "sec-websocket-version" => version = value == "13",
+            _ => {}
+        }
+    }
+    match key {
+        Some(ref key) if request.starts_with("GET ") && upgrade && version => {
+            write!(
+                output,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            )?;
+            output.flush()
+        }
+        _ => bad_request(output, invalid("not a websocket handshake")),
+    }
+}
+
+/// Reads a frame. Payloads longer than `MAX_MESSAGE` are an error, see
+/// `too_long`, and are left unread.
+pub fn read_frame<R: Read>(input: &mut R) -> io::Result<Frame> {
+    let mut head = [0; 2];
+    input.read_exact(&mut head)?;
+    let opcode = Opcode::from_u8(head[0] & 0x0F).ok_or_else(|| invalid("unknown opcode"))?;
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut b = [0; 2];
+            input.read_exact(&mut b)?;
+            u64::from(u16::from_be_bytes(b))
+        }
+        127 => {
+            let mut b = [0; 8];
+            input.read_exact(&mut b)?;
+            u64::from_be_bytes(b)
+        }
+        n => u64::from(n),
+    };
+    if len > MAX_MESSAGE as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, TooLong));
+    }
+    let mut mask = [0; 4];
+    if masked {
+        input.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    input.read_exact(&mut payload)?;
+    if masked {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+    Ok(Frame {
+        fin: head[0] & 0x80 != 0,
+        opcode,
+        masked,
+        payload,
+    })
+}
+
+/// Writes a frame, masked with `mask` if given. Servers do not mask their
+/// frames, clients must.
+pub fn write_frame<W: Write>(output: &mut W, frame: &Frame, mask: Option<[u8; 4]>) -> io::Result<()> {
+    let mut head = vec![(if frame.fin { 0x80 } else { 0 }) | frame.opcode.to_u8()];
+    let bit = if mask.is_some() { 0x80 } else { 0 };
+    let len = frame.payload.len();
+    if len < 126 {
+        head.push(bit | len as u8);
+    } else if len <= 0xFFFF {
+        head.push(bit | 126);
+        head.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        head.push(bit | 127);
+        head.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    let mut payload = frame.payload.clone();
+    if let Some(mask) = mask {
+        head.extend_from_slice(&mask);
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+    output.write_all(&head)?;
+    output.write_all(&payload)?;
+    output.flush()
+}
+
+/// Sending side of a connection, shared by the reader of the frames and
+/// the writer of the replies. Nothing is sent after a close frame.
+struct Output<W> {
+    w: W,
+    closed: bool,
+}
+
+fn send<W: Write>(output: &Mutex<Output<W>>, frame: &Frame) -> io::Result<()> {
+    let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+    if output.closed {
+        return Ok(());
+    }
+    output.closed = frame.opcode == Opcode::Close;
+    write_frame(&mut output.w, frame, None)
+}
+
+/// Sends a close frame with status `code`.
+fn close<W: Write>(output: &Mutex<Output<W>>, code: u16) -> io::Result<()> {
+    send(output, &Frame::new(Opcode::Close, &code.to_be_bytes()))
+}
+
+/// Serves one websocket connection, from the handshake until the client
+/// closes it and the queued commands of it are done.
+pub fn serve<R: BufRead, W: Write + Send>(mut input: R, mut output: W, queue: &Queue) -> io::Result<()> {
+    handshake(&mut input, &mut output)?;
+    let output = Mutex::new(Output { w: output, closed: false });
+    let (tx, rx) = channel::<String>();
+    thread::scope(|scope| {
+        let out = &output;
+        let writer = scope.spawn(move || -> io::Result<()> {
+            for reply in rx {
+                let reply = format!("{}\n", reply);
+                send(out, &Frame::new(Opcode::Text, reply.as_bytes()))?;
+            }
+            Ok(())
+        });
+        let read = receive(&mut input, out, &mut Handler::new(queue, tx));
+        let written = writer.join().unwrap_or_else(|_| Err(io::Error::other("writer panicked")));
+        read.and(written)
+    })
+}
+
+/// Reads frames and runs the commands in them until the connection is
+/// closed.
+fn receive<R: BufRead, W: Write>(input: &mut R, output: &Mutex<Output<W>>, handler: &mut Handler) -> io::Result<()> {
+    // Text of the message being received, if its first frame is read.
+    let mut message: Option<Vec<u8>> = None;
+    loop {
+        let frame = match read_frame(input) {
+            Ok(f) => f,
+            Err(ref e) if too_long(e) => return close(output, 1009),
+            Err(e) => return Err(e),
+        };
+        if !frame.masked {
+            return close(output, 1002);
+        }
+        if frame.opcode.is_control() && (!frame.fin || frame.payload.len() > 125) {
+            return close(output, 1002);
+        }
+        match frame.opcode {
+            Opcode::Ping => send(output, &Frame::new(Opcode::Pong, &frame.payload))?,
+            Opcode::Pong => {}
+            Opcode::Close => {
+                let code = if frame.payload.len() >= 2 { &frame.payload[..2] } else { &[][..] };
+                return send(output, &Frame::new(Opcode::Close, code));
+            }
+            Opcode::Binary => return close(output, 1003),
+            Opcode::Text | Opcode::Continuation => {
+                if (frame.opcode == Opcode::Text) == message.is_some() {
+                    return close(output, 1002);
+                }
+                let mut text = message.take().unwrap_or_default();
+                if text.len() + frame.payload.len() > MAX_MESSAGE {
+                    return close(output, 1009);
+                }
+                text.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    message = Some(text);
+                    continue;
+                }
+                let text = match String::from_utf8(text) {
+                    Ok(t) => t,
+                    Err(_) => return close(output, 1007),
+                };
+                for line in text.lines() {
+                    handler.handle(line)?;
+                }
+            }
+        }
+    }
+}
+
+/// Accepts websocket connections on `addr` and serves each in its own
+/// thread. Sessions and the queue are shared by the connections.
+pub fn listen<A: ToSocketAddrs>(addr: A, queue: Arc<Queue>) -> io::Result<()> {
+    accept(TcpListener::bind(addr)?, queue)
+}
+
+/// Serves websocket connections of `listener`, see `listen`.
+pub fn accept(listener: TcpListener, queue: Arc<Queue>) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream: TcpStream = stream?;
+        let queue = queue.clone();
+        thread::spawn(move || -> io::Result<()> {
+            let input = BufReader::new(stream.try_clone()?);
+            serve(input, stream, &queue)
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kwp::session::Sessions;
+    use std::io::Cursor;
+
+    const MASK: Option<[u8; 4]> = Some([1, 2, 3, 4]);
+
+    fn text(s: &str) -> Frame {
+        Frame::new(Opcode::Text, s.as_bytes())
+    }
+
+    /// Opens a connection to `addr` like a browser would.
+    fn connect(addr: &str) -> (BufReader<TcpStream>, TcpStream) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /kwp HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            addr
+        )
+        .unwrap();
+        let mut input = BufReader::new(stream.try_clone().unwrap());
+        let mut response = String::new();
+        while !response.ends_with("\r\n\r\n") {
+            input.read_line(&mut response).unwrap();
+        }
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        (input, stream)
+    }
+
+    fn reply(input: &mut BufReader<TcpStream>) -> String {
+        let f = read_frame(input).unwrap();
+        assert_eq!((true, Opcode::Text, false), (f.fin, f.opcode, f.masked));
+        String::from_utf8(f.payload).unwrap()
+    }
+
+    #[test]
+    fn test_accept_key() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+    #[test]
+    fn test_frames() {
+        for len in &[0, 5, 125, 126, 300, MAX_MESSAGE] {
+            let f = Frame::new(Opcode::Binary, &vec![7; *len]);
+            let mut buf = Vec::new();
+            write_frame(&mut buf, &f, MASK).unwrap();
+            let read = read_frame(&mut Cursor::new(&buf)).unwrap();
+            assert_eq!(Frame { masked: true, ..f }, read);
+        }
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Frame::new(Opcode::Binary, &vec![0; MAX_MESSAGE + 1]), None).unwrap();
+        assert!(too_long(&read_frame(&mut Cursor::new(&buf)).unwrap_err()));
+        assert!(!too_long(&read_frame(&mut Cursor::new(&[0x83, 0])).unwrap_err()));
+    }
+    #[test]
+    fn test_bad_handshake() {
+        let queue = Queue::new(0, Arc::new(Mutex::new(Sessions::new())));
+        let mut output = Vec::new();
+        let input = "GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        assert!(serve(input.as_bytes(), &mut output, &queue).is_err());
+        assert!(String::from_utf8(output).unwrap().starts_with("HTTP/1.1 400 "));
+
+        // Endless lines and headers.
+        for input in &[
+            format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE)),
+            format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADERS + 1)),
+        ] {
+            let mut output = Vec::new();
+            assert!(serve(input.as_bytes(), &mut output, &queue).is_err());
+            assert!(String::from_utf8(output).unwrap().starts_with("HTTP/1.1 400 "));
+        }
+    }
+    #[test]
+    fn test_too_long() {
+        let queue = Queue::new(0, Arc::new(Mutex::new(Sessions::new())));
+        let mut input = "GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: a\r\n\
+                         Sec-WebSocket-Version: 13\r\n\r\n"
+            .as_bytes()
+            .to_vec();
+        write_frame(&mut input, &text(&"a".repeat(MAX_MESSAGE + 1)), MASK).unwrap();
+        let mut output = Vec::new();
+        serve(&input[..], &mut output, &queue).unwrap();
+        let start = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let close = read_frame(&mut Cursor::new(&output[start..])).unwrap();
+        assert_eq!(Frame::new(Opcode::Close, &1009u16.to_be_bytes()), close);
+    }
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let queue = Arc::new(Queue::new(1, Arc::new(Mutex::new(Sessions::new()))));
+        thread::spawn(move || accept(listener, queue));
+
+        let (mut input, mut output) = connect(&addr);
+        let (mut other_input, mut other_output) = connect(&addr);
+        write_frame(&mut output, &text("new connection db sqlite addr :memory:\ninfo\n"), MASK).unwrap();
+        assert_eq!("cmd 1 queued\n", reply(&mut input));
+        let pid = reply(&mut input).trim().replace("id 1 connection estb pid ", "");
+        assert_eq!("cmd 2 queued\n", reply(&mut input));
+        assert!(reply(&mut input).ends_with(&format!(" {} 0\n", pid)));
+
+        // Every connection has its own session.
+        write_frame(&mut other_output, &text("info\n"), MASK).unwrap();
+        assert_eq!("cmd 1 queued\n", reply(&mut other_input));
+        assert_eq!("id 1 error 401 no connection\n", reply(&mut other_input));
+
+        // A message in two frames.
+        let mut first = text("inf");
+        first.fin = false;
+        write_frame(&mut output, &first, MASK).unwrap();
+        write_frame(&mut output, &Frame::new(Opcode::Continuation, b"o"), MASK).unwrap();
+        assert_eq!("cmd 3 queued\n", reply(&mut input));
+        assert!(reply(&mut input).starts_with("id 3 info "));
+
+        write_frame(&mut output, &Frame::new(Opcode::Ping, b"hei"), MASK).unwrap();
+        assert_eq!(Frame::new(Opcode::Pong, b"hei"), read_frame(&mut input).unwrap());
+        write_frame(&mut output, &Frame::new(Opcode::Close, &1000u16.to_be_bytes()), MASK).unwrap();
+        assert_eq!(Frame::new(Opcode::Close, &[3, 232]), read_frame(&mut input).unwrap());
+
+        write_frame(&mut other_output, &text("info\n"), None).unwrap();
+        assert_eq!(Frame::new(Opcode::Close, &[3, 234]), read_frame(&mut other_input).unwrap());
+    }
+}
@@ -1,5 +1,18 @@
 #[cfg(test)]
 #[macro_use]
 extern crate pretty_assertions;
+#[cfg(test)]
+extern crate quickcheck;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate csv;
+extern crate rusqlite;
+extern crate serde_json;
+extern crate sha1_smol;
+extern crate base64;
+extern crate getrandom;
 pub mod kipac;
 pub mod calc;
+pub mod competition;
+pub mod kwp;
@@ -4,15 +4,36 @@ use std::io;
 
 use kilac::*;
 use calc::ctx::EmptyCtx;
+use calc::error::KilaError;
+
+/// Prints the error and underlines the part of the formula it points at.
+fn report(s: &str, e: KilaError) {
+    let (start, end) = e.span().columns(s);
+    eprintln!("{}", s);
+    eprintln!("{}{}", " ".repeat(start), "^".repeat(usize::max(end - start, 1)));
+    eprintln!("{}", e.kwp());
+}
 
 fn main() {
     let mut eva = String::new();
-    while let Ok(_) = io::stdin().read_line(&mut eva) {
-        let lexed = calc::lexer::lex(&eva.clone().trim());
-        let ast = calc::parser::parse(lexed,EmptyCtx).ok().unwrap();
-        match calc::eval(ast,EmptyCtx).ok().unwrap() {
-            kilac::calc::Value::Num(n) => println!("{}", n),
-            kilac::calc::Value::Vec(n) => println!("{:?}", n)
+    while let Ok(n) = io::stdin().read_line(&mut eva) {
+        if n == 0 {
+            break;
+        }
+        let s = eva.trim();
+        let lexed = match calc::lexer::lex(s) {
+            Ok(l) => l,
+            Err(errs) => {
+                for e in errs {
+                    report(s, e);
+                }
+                eva = String::new();
+                continue;
+            }
+        };
+        match calc::parser::parse(lexed, EmptyCtx).and_then(|ast| calc::eval(ast, EmptyCtx)) {
+            Ok(v) => println!("{}", v),
+            Err(e) => report(s, e),
         }
         eva = String::new();
     }
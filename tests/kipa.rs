@@ -0,0 +1,129 @@
+//! Kipa compatibility corpus. Every file in tests/corpus lists inputs of a
+//! team and Kipa formulas with their expected results. Each formula is run
+//! through `lex`, `parse` and `eval` with the inputs as context.
+extern crate kilac;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use kilac::calc::ctx::KilaCtx;
+use kilac::calc::error::KilaError;
+use kilac::calc::getter::Getter;
+use kilac::calc::lexer::lex;
+use kilac::calc::parser::parse;
+use kilac::calc::span::Span;
+use kilac::calc::{eval, Value};
+
+/// Inputs of a corpus file by getter, written the way `Getter` displays.
+#[derive(Clone)]
+struct Fixture(Rc<HashMap<String, Value>>);
+
+impl KilaCtx for Fixture {
+    fn get(&self, g: &Getter) -> Result<Value, KilaError> {
+        let g = g.to_string();
+        self.0.get(&g).cloned().ok_or_else(|| KilaError::UnknownIdentifier(g, Span::default()))
+    }
+}
+
+/// Expected result of a formula.
+#[derive(Debug, Clone, Copy)]
+enum Expected {
+    Num(f64),
+    Empty,
+    Error(u32),
+}
+
+/// Parses a single input: a number, a clock time or `-` for empty.
+fn scalar(s: &str) -> Value {
+    let s = s.trim();
+    if s == "-" {
+        return Value::Empty;
+    }
+    if s.contains(':') {
+        let secs = s.split(':').fold(0.0, |acc, p| acc * 60.0 + p.parse::<f64>().unwrap());
+        return Value::Time(secs * if s.matches(':').count() == 1 { 60.0 } else { 1.0 });
+    }
+    Value::Num(s.parse().unwrap_or_else(|_| panic!("bad input {}", s)))
+}
+
+/// Parses an input, which may be a list `[a, b, ...]`.
+fn input(s: &str) -> Value {
+    let s = s.trim();
+    if s.starts_with('[') && s.ends_with(']') {
+        Value::List(s[1..s.len() - 1].split(',').map(scalar).collect())
+    } else {
+        scalar(s)
+    }
+}
+
+fn expected(s: &str) -> Expected {
+    let s = s.trim();
+    if s == "-" {
+        Expected::Empty
+    } else if let Some(code) = s.strip_prefix("error ") {
+        Expected::Error(code.parse().unwrap())
+    } else {
+        Expected::Num(s.parse().unwrap_or_else(|_| panic!("bad result {}", s)))
+    }
+}
+
+fn run(f: &str, ctx: Fixture) -> Result<Value, KilaError> {
+    let lexed = lex(f).map_err(KilaError::merge)?;
+    eval(parse(lexed, ctx.clone())?, ctx)
+}
+
+fn matches(e: &Expected, res: &Result<Value, KilaError>) -> bool {
+    match (*e, res) {
+        (Expected::Empty, &Ok(Value::Empty)) => true,
+        (Expected::Error(c), Err(err)) => err.code() == c,
+        (Expected::Num(n), Ok(v)) => match v.as_num() {
+            Some(v) => (v - n).abs() <= 1e-9 * f64::max(1.0, n.abs()),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Runs every formula of a corpus file, returns a line for every failure.
+fn check(path: &Path) -> Vec<String> {
+    let text = fs::read_to_string(path).unwrap();
+    let mut inputs = HashMap::new();
+    let mut cases = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(l) = line.strip_prefix("let ") {
+            let mut kv = l.splitn(2, '=');
+            let k = Getter::parse(kv.next().unwrap().trim()).unwrap().to_string();
+            inputs.insert(k, input(kv.next().expect("let without =")));
+        } else {
+            let mut fe = line.rsplitn(2, "=>");
+            let e = expected(fe.next().unwrap());
+            let f = fe.next().unwrap_or_else(|| panic!("{}:{} missing =>", path.display(), i + 1));
+            cases.push((i + 1, f.trim().to_string(), e));
+        }
+    }
+    let ctx = Fixture(Rc::new(inputs));
+    let mut failures = Vec::new();
+    for (line, f, e) in cases {
+        let res = run(&f, ctx.clone());
+        if !matches(&e, &res) {
+            failures.push(format!("{}:{}: {} expected {:?} got {:?}", path.display(), line, f, e, res));
+        }
+    }
+    failures
+}
+
+#[test]
+fn kipa_corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut files: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+    files.sort();
+    assert!(!files.is_empty());
+    let failures: Vec<String> = files.iter().flat_map(|f| check(f)).collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}